@@ -0,0 +1,76 @@
+use std::io;
+
+use time::{self, Duration};
+
+use conversions::downsample_yuyv_420p;
+use pipeline::PipelineConfig;
+use source::{FrameSource, Pacing, PatternSource};
+use webp::{self, EncodeError, WebpEncoder};
+
+/// Transcode times over a run of frames.
+struct Timings {
+    total: Duration,
+    min: Option<Duration>,
+    max: Duration,
+    bytes: usize,
+    frames: u32,
+}
+
+impl Timings {
+    fn new() -> Timings {
+        Timings { total: Duration::zero(), min: None, max: Duration::zero(), bytes: 0, frames: 0 }
+    }
+
+    fn add(&mut self, elapsed: Duration, bytes: usize) {
+        self.total = self.total + elapsed;
+        self.min = Some(match self.min {
+            Some(min) if min < elapsed => min,
+            _ => elapsed,
+        });
+        if self.max < elapsed {
+            self.max = elapsed;
+        }
+        self.bytes += bytes;
+        self.frames += 1;
+    }
+
+    fn report(&self, label: &str) {
+        if self.frames == 0 {
+            return;
+        }
+        println!("{}: {} frames, per frame mean {} min {} max {}, mean size {} bytes",
+            label, self.frames, self.total / self.frames as i32,
+            self.min.unwrap_or(Duration::zero()), self.max, self.bytes / self.frames as usize);
+    }
+}
+
+/// Encodes `frames` synthetic frames at the configured size and encoder
+/// settings, once through `webp::reencode` (fresh encoder every frame) and
+/// once through a reused `WebpEncoder`, and prints the per-frame transcode
+/// times of each.
+pub fn run(config: &PipelineConfig, frames: u64) -> io::Result<()> {
+    let to_io = |err: EncodeError| io::Error::new(io::ErrorKind::Other, err.to_string());
+
+    let mut source = PatternSource::new(config.width, config.height, config.frame_interval(), Pacing::FullSpeed)
+        .with_frame_limit(frames);
+    let mut encoder = try!(WebpEncoder::new(config.encoder).map_err(&to_io));
+
+    let mut oneshot = Timings::new();
+    let mut reused = Timings::new();
+    while let Some(frame) = try!(source.next_frame()) {
+        let yuv = downsample_yuyv_420p(&frame.surface);
+
+        let start = time::get_time();
+        let len = try!(webp::reencode(&yuv, &config.encoder).map_err(&to_io)).len();
+        oneshot.add(time::get_time() - start, len);
+
+        let start = time::get_time();
+        let len = try!(encoder.encode(&yuv).map_err(&to_io)).len();
+        reused.add(time::get_time() - start, len);
+    }
+
+    println!("encoding {}x{} frames, {:?}", config.width, config.height, config.encoder);
+    oneshot.report("webp::reencode");
+    reused.report("WebpEncoder");
+    Ok(())
+}
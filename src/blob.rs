@@ -0,0 +1,104 @@
+/// A connected group of lit pixels in the motion mask.
+#[derive(Clone, Debug)]
+pub struct Blob {
+    pub area: usize,
+    pub centroid: (f32, f32),
+    pub bbox: BoundingBox,
+}
+
+/// Pixel bounds, `x0..x1` by `y0..y1`, exclusive at the far edges.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BoundingBox {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl BoundingBox {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    pub fn area(&self) -> u32 {
+        self.width() * self.height()
+    }
+
+    /// Intersection over union, 0.0 for disjoint boxes.
+    pub fn iou(&self, other: &BoundingBox) -> f32 {
+        let x0 = ::std::cmp::max(self.x0, other.x0);
+        let y0 = ::std::cmp::max(self.y0, other.y0);
+        let x1 = ::std::cmp::min(self.x1, other.x1);
+        let y1 = ::std::cmp::min(self.y1, other.y1);
+        if x1 <= x0 || y1 <= y0 {
+            return 0.0;
+        }
+        let inter = (x1 - x0) * (y1 - y0);
+        inter as f32 / (self.area() + other.area() - inter) as f32
+    }
+}
+
+/// 8-connected components of `lit`, a row-major `width * height` mask,
+/// dropping any smaller than `min_area` pixels.
+pub fn find_blobs(width: u32, height: u32, lit: &[bool], min_area: usize) -> Vec<Blob> {
+    let (w, h) = (width as usize, height as usize);
+    debug_assert_eq!(lit.len(), w * h);
+
+    let mut visited = vec![false; lit.len()];
+    let mut stack = Vec::new();
+    let mut blobs = Vec::new();
+
+    for start in 0..lit.len() {
+        if !lit[start] || visited[start] {
+            continue;
+        }
+
+        let mut area = 0;
+        let (mut sum_x, mut sum_y) = (0u64, 0u64);
+        let mut bbox = BoundingBox {
+            x0: (start % w) as u32,
+            y0: (start / w) as u32,
+            x1: (start % w) as u32 + 1,
+            y1: (start / w) as u32 + 1,
+        };
+
+        visited[start] = true;
+        stack.push(start);
+        while let Some(idx) = stack.pop() {
+            let (x, y) = (idx % w, idx / w);
+            area += 1;
+            sum_x += x as u64;
+            sum_y += y as u64;
+            bbox.x0 = ::std::cmp::min(bbox.x0, x as u32);
+            bbox.y0 = ::std::cmp::min(bbox.y0, y as u32);
+            bbox.x1 = ::std::cmp::max(bbox.x1, x as u32 + 1);
+            bbox.y1 = ::std::cmp::max(bbox.y1, y as u32 + 1);
+
+            let (nx0, nx1) = (x.saturating_sub(1), ::std::cmp::min(x + 2, w));
+            let (ny0, ny1) = (y.saturating_sub(1), ::std::cmp::min(y + 2, h));
+            for ny in ny0..ny1 {
+                for nx in nx0..nx1 {
+                    let nidx = ny * w + nx;
+                    if lit[nidx] && !visited[nidx] {
+                        visited[nidx] = true;
+                        stack.push(nidx);
+                    }
+                }
+            }
+        }
+
+        if min_area <= area {
+            blobs.push(Blob {
+                area: area,
+                centroid: (sum_x as f32 / area as f32, sum_y as f32 / area as f32),
+                bbox: bbox,
+            });
+        }
+    }
+
+    blobs
+}
@@ -0,0 +1,490 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use getopts;
+use time;
+
+use mask::{self, Zone, ZoneShape};
+use tamper::TamperConfig;
+use threshold::AdaptiveThreshold;
+use tripwire::Tripwire;
+use webp::EncoderConfig;
+use motion::MotionConfig;
+use pipeline::{PipelineConfig, SourceKind, Streams};
+use source::Pacing;
+
+// Largest zone or tripwire coordinate magnitude accepted, in pixels.
+const MAX_COORDINATE: f64 = 1e6;
+
+pub enum Command {
+    /// A single pipeline described by flags, and how often to report status.
+    Run(PipelineConfig, Option<Duration>),
+    /// Pipelines described by a configuration file.
+    RunConfigFile(String, Option<Duration>),
+    /// Time WebP encoding of this many synthetic frames with the pipeline's
+    /// size and encoder settings.
+    BenchEncode(PipelineConfig, u64),
+    Help(String),
+}
+
+#[derive(Debug)]
+pub struct UsageError {
+    message: String,
+}
+
+impl UsageError {
+    fn new<S: Into<String>>(message: S) -> UsageError {
+        UsageError { message: message.into() }
+    }
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+fn option_spec() -> getopts::Options {
+    let mut opts = getopts::Options::new();
+    opts.optopt("c", "config", "run the pipelines described in a TOML file", "FILE");
+    opts.optopt("d", "device", "V4L2 capture device (default /dev/video0)", "PATH");
+    opts.optopt("", "replay", "replay a recorded .yuv422p file instead of a camera", "FILE");
+    opts.optflag("", "pattern", "capture a synthetic test pattern instead of a camera");
+    opts.optflag("", "realtime", "pace --replay/--pattern frames at the frame interval");
+    opts.optopt("r", "resolution", "capture resolution (default 1280x960)", "WxH");
+    opts.optopt("f", "fps", "frame rate, whole or as a fraction (default 5)", "RATE");
+    opts.optopt("", "format", "camera pixel format (default YUYV)", "FOURCC");
+    opts.optopt("", "streams", "outputs to write: fullsize, raw, edge, heatmap (default fullsize,raw,edge)", "LIST");
+    opts.optopt("", "heatmap-interval", "seconds between heatmap rewrites (default 300)", "SECS");
+    opts.optopt("", "keep-shift", "log2 of raw output bytes kept on disk (default 27)", "N");
+    opts.optopt("", "punch-shift", "log2 of the hole-punch granularity (default 26)", "N");
+    opts.optopt("q", "quality", "WebP quality, 0 to 100 (default 70)", "Q");
+    opts.optopt("", "preset", "WebP preset: default, picture, photo, drawing, icon, text (default picture)", "NAME");
+    opts.optopt("", "method", "WebP speed/size trade-off, 0 (fast) to 6 (small)", "N");
+    opts.optflag("", "lossless", "encode WebP losslessly");
+    opts.optopt("", "target-size", "aim for this many bytes per WebP frame", "BYTES");
+    opts.optopt("", "target-psnr", "aim for this WebP PSNR", "DB");
+    opts.optopt("", "filter-strength", "WebP deblocking strength, 0 to 100", "N");
+    opts.optopt("", "segments", "WebP segments, 1 to 4", "N");
+    opts.optopt("", "encode-threads", "WebP encoding worker threads (default 2)", "N");
+    opts.optopt("", "smoothing", "none, average3x3, gaussian:SIGMA or box:RADIUS (default average3x3)", "KERNEL");
+    opts.optopt("", "edge-operator", "sobel, scharr or laplacian (default sobel)", "NAME");
+    opts.optopt("", "edge-threshold", "edge difference counted as motion, 0 to 255 (default 96)", "N");
+    opts.optopt("", "morphology", "operations applied to the motion mask, e.g. open,close:disk:2", "LIST");
+    opts.optopt("", "adaptive-threshold", "follow the noise floor instead, clamped to this range", "MIN,MAX");
+    opts.optopt("", "min-lit-fraction", "fraction of the frame that must be lit to trigger", "F");
+    opts.optopt("", "pre-roll", "seconds kept from before a trigger (default 2)", "SECS");
+    opts.optopt("", "post-roll", "seconds kept after the last trigger (default 2)", "SECS");
+    opts.optopt("", "detector", "edge-diff, running-average or gaussian-mixture (default edge-diff)", "NAME");
+    opts.optopt("", "learning-rate", "background model update rate, 0 to 1 (default 0.05)", "R");
+    opts.optopt("", "min-blob-area", "smallest group of lit pixels counted as motion (default 1)", "PIXELS");
+    opts.optopt("", "min-track-frames", "frames a moving region must persist to count (default 1)", "N");
+    opts.optopt("", "illumination-threshold", "mean luma shift ignored as a lighting change, 0 for never (default 20)", "LEVELS");
+    opts.optopt("", "tamper-hold", "report the camera covered or moved after this many seconds", "SECS");
+    opts.optmulti("", "tripwire", "record only when a track crosses this line; repeatable", "X0,Y0,X1,Y1[,DIR]");
+    opts.optopt("", "mask", "only count motion where this PGM (at capture size) is non-zero", "FILE");
+    opts.optopt("", "status-interval", "seconds between per-camera status reports, 0 for none (default 60)", "SECS");
+    opts.optopt("", "bench-encode", "time WebP encoding of this many test frames and exit", "FRAMES");
+    opts.optflag("h", "help", "print this help and exit");
+    opts
+}
+
+fn usage(program: &str, opts: &getopts::Options) -> String {
+    let brief = format!(concat!("Usage: {0} [OPTIONS] PREFIX\n",
+        "       {0} [OPTIONS] DEVICE PREFIX\n",
+        "       {0} --config FILE\n",
+        "       {0} [OPTIONS] --bench-encode FRAMES"), program);
+    opts.usage(&brief)
+}
+
+pub fn parse_args(args: &[String]) -> Result<Command, UsageError> {
+    let program = args.first().map(|s| &s[..]).unwrap_or("camcap");
+    let opts = option_spec();
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(matches) => matches,
+        Err(err) => return Err(UsageError::new(err.to_string())),
+    };
+
+    if matches.opt_present("help") {
+        return Ok(Command::Help(usage(program, &opts)));
+    }
+
+    let status_interval = match try!(parse_number("status-interval", &matches, 60)) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+
+    if let Some(path) = matches.opt_str("config") {
+        let single_pipeline_opts = ["device", "replay", "pattern", "realtime", "resolution", "fps",
+            "format", "streams", "heatmap-interval", "keep-shift", "punch-shift", "quality",
+            "preset", "method", "lossless", "target-size", "target-psnr", "filter-strength",
+            "segments", "encode-threads", "smoothing", "edge-operator", "edge-threshold",
+            "morphology", "adaptive-threshold", "min-lit-fraction", "pre-roll", "post-roll", "mask",
+            "detector", "learning-rate", "min-blob-area", "min-track-frames",
+            "illumination-threshold", "tamper-hold", "tripwire", "bench-encode"];
+        for name in single_pipeline_opts.iter() {
+            if matches.opt_present(name) {
+                return Err(UsageError::new(format!(
+                    "--{} cannot be combined with --config; set it in {} instead", name, path)));
+            }
+        }
+        if !matches.free.is_empty() {
+            return Err(UsageError::new(format!(
+                "unexpected argument {:?} alongside --config", matches.free[0])));
+        }
+        return Ok(Command::RunConfigFile(path, status_interval));
+    }
+
+    let bench_frames: Option<u64> = try!(parse_optional("bench-encode", &matches));
+
+    let (positional_device, prefix) = match matches.free.len() {
+        1 => (None, matches.free[0].clone()),
+        2 => (Some(matches.free[0].clone()), matches.free[1].clone()),
+        0 if bench_frames.is_some() => (None, String::new()),
+        0 => return Err(UsageError::new("missing output PREFIX")),
+        _ => return Err(UsageError::new(format!(
+            "unexpected argument {:?}", matches.free[2]))),
+    };
+
+    let defaults = PipelineConfig::default();
+    let mut config = PipelineConfig {
+        source: try!(parse_source(&matches, positional_device, &defaults.source)),
+        prefix: prefix,
+        ..defaults
+    };
+
+    if matches.opt_present("realtime") {
+        config.pacing = Pacing::RealTime;
+    }
+    if let Some(val) = matches.opt_str("resolution") {
+        let (width, height) = try!(parse_resolution(&val));
+        config.width = width;
+        config.height = height;
+    }
+    if let Some(val) = matches.opt_str("fps") {
+        config.interval = try!(parse_fps(&val));
+    }
+    if let Some(val) = matches.opt_str("format") {
+        config.fourcc = try!(parse_fourcc(&val));
+    }
+    if let Some(val) = matches.opt_str("streams") {
+        config.streams = try!(Streams::parse_list(val.split(',')).map_err(UsageError::new));
+    }
+    if let Some(val) = matches.opt_str("heatmap-interval") {
+        config.heatmap_interval = try!(parse_seconds("heatmap-interval", &val));
+    }
+
+    config.keep_shift = try!(parse_number("keep-shift", &matches, config.keep_shift));
+    config.punch_shift = try!(parse_number("punch-shift", &matches, config.punch_shift));
+    try!(check_shifts(config.keep_shift, config.punch_shift));
+
+    if let Some(val) = matches.opt_str("preset") {
+        config.encoder.preset = try!(val.parse().map_err(UsageError::new));
+    }
+    config.encoder.quality = try!(parse_number("quality", &matches, config.encoder.quality));
+    config.encoder.lossless = matches.opt_present("lossless");
+    config.encoder.method = try!(parse_optional("method", &matches));
+    config.encoder.target_size = try!(parse_optional("target-size", &matches));
+    config.encoder.target_psnr = try!(parse_optional("target-psnr", &matches));
+    config.encoder.filter_strength = try!(parse_optional("filter-strength", &matches));
+    config.encoder.segments = try!(parse_optional("segments", &matches));
+    try!(check_encoder(&config.encoder));
+    config.encode_threads = try!(parse_number("encode-threads", &matches, config.encode_threads));
+    try!(check_encode_threads(config.encode_threads));
+
+    if let Some(val) = matches.opt_str("smoothing") {
+        config.motion.smoothing = try!(val.parse().map_err(UsageError::new));
+    }
+    if let Some(val) = matches.opt_str("edge-operator") {
+        config.motion.edge_operator = try!(val.parse().map_err(UsageError::new));
+    }
+    config.motion.edge_threshold = try!(parse_number("edge-threshold", &matches, config.motion.edge_threshold));
+    if let Some(val) = matches.opt_str("morphology") {
+        for step in val.split(',') {
+            config.motion.morphology.push(try!(step.parse().map_err(UsageError::new)));
+        }
+    }
+    if let Some(val) = matches.opt_str("adaptive-threshold") {
+        let invalid = || UsageError::new(format!(
+            "invalid value {:?} for --adaptive-threshold, expected MIN,MAX", val));
+        let mut fields = val.splitn(2, ',');
+        let min = try!(fields.next().and_then(|f| f.trim().parse().ok()).ok_or_else(&invalid));
+        let max = try!(fields.next().and_then(|f| f.trim().parse().ok()).ok_or_else(&invalid));
+        config.motion.adaptive_threshold = Some(AdaptiveThreshold { min: min, max: max, ..Default::default() });
+    }
+    config.motion.min_lit_fraction = try!(parse_number("min-lit-fraction", &matches, config.motion.min_lit_fraction));
+    if let Some(val) = matches.opt_str("pre-roll") {
+        config.motion.pre_roll = try!(parse_seconds("pre-roll", &val));
+    }
+    if let Some(val) = matches.opt_str("post-roll") {
+        config.motion.post_roll = try!(parse_seconds("post-roll", &val));
+    }
+    if let Some(val) = matches.opt_str("detector") {
+        config.motion.detector = try!(val.parse().map_err(UsageError::new));
+    }
+    config.motion.learning_rate = try!(parse_number("learning-rate", &matches, config.motion.learning_rate));
+    config.motion.min_blob_area = try!(parse_number("min-blob-area", &matches, config.motion.min_blob_area));
+    config.motion.min_track_frames = try!(parse_number("min-track-frames", &matches, config.motion.min_track_frames));
+    if matches.opt_present("illumination-threshold") {
+        let levels: f32 = try!(parse_number("illumination-threshold", &matches, 0.0));
+        config.motion.illumination_threshold = if levels == 0.0 { None } else { Some(levels) };
+    }
+    try!(check_motion(&config.motion));
+
+    if let Some(val) = matches.opt_str("tamper-hold") {
+        config.motion.tamper = Some(TamperConfig {
+            hold: try!(parse_seconds("tamper-hold", &val)),
+            ..Default::default()
+        });
+    }
+
+    if let Some(path) = matches.opt_str("mask") {
+        let (width, height, data) = try!(mask::load_pgm(&path)
+            .map_err(|e| UsageError::new(e.to_string())));
+        if (width, height) != (config.width, config.height) {
+            return Err(UsageError::new(format!("--mask {} is {}x{}, but the capture resolution is {}x{}",
+                path, width, height, config.width, config.height)));
+        }
+        config.motion.zones.push(Zone {
+            name: "mask".to_string(),
+            shape: ZoneShape::Bitmap { width: width, height: height, data: data },
+            exclude: false,
+            threshold: None,
+        });
+    }
+
+    for (index, spec) in matches.opt_strs("tripwire").iter().enumerate() {
+        config.motion.tripwires.push(try!(parse_tripwire(&format!("tripwire{}", index), spec)));
+    }
+
+    if let Some(frames) = bench_frames {
+        return Ok(Command::BenchEncode(config, frames));
+    }
+
+    Ok(Command::Run(config, status_interval))
+}
+
+/// Parses `X0,Y0,X1,Y1`, optionally followed by `,forward` or `,backward`.
+pub fn parse_tripwire(name: &str, val: &str) -> Result<Tripwire, UsageError> {
+    let invalid = || UsageError::new(format!(
+        "invalid tripwire {:?}, expected X0,Y0,X1,Y1 optionally followed by ,forward or ,backward", val));
+
+    let fields: Vec<&str> = val.split(',').map(|f| f.trim()).collect();
+    if fields.len() < 4 || 5 < fields.len() {
+        return Err(invalid());
+    }
+    let mut coords = [0f32; 4];
+    for (coord, field) in coords.iter_mut().zip(fields.iter()) {
+        let v = try!(field.parse().map_err(|_| invalid()));
+        *coord = try!(check_coordinate("tripwire", v));
+    }
+    let direction = match fields.get(4) {
+        Some(dir) => Some(try!(dir.parse().map_err(UsageError::new))),
+        None => None,
+    };
+
+    Ok(Tripwire {
+        name: name.to_string(),
+        from: (coords[0], coords[1]),
+        to: (coords[2], coords[3]),
+        direction: direction,
+    })
+}
+
+pub fn check_shifts(keep_shift: u8, punch_shift: u8) -> Result<(), UsageError> {
+    if 62 < keep_shift {
+        return Err(UsageError::new(format!("keep-shift {} is too large (max 62)", keep_shift)));
+    }
+    if keep_shift < punch_shift {
+        return Err(UsageError::new(format!(
+            "punch-shift {} must not exceed keep-shift {}", punch_shift, keep_shift)));
+    }
+    Ok(())
+}
+
+pub fn check_encoder(encoder: &EncoderConfig) -> Result<(), UsageError> {
+    if !(0.0 <= encoder.quality && encoder.quality <= 100.0) {
+        return Err(UsageError::new(format!("quality {} is outside 0 to 100", encoder.quality)));
+    }
+    if let Some(method) = encoder.method {
+        if 6 < method {
+            return Err(UsageError::new(format!("method {} is outside 0 to 6", method)));
+        }
+    }
+    if let Some(target_psnr) = encoder.target_psnr {
+        if !(0.0 < target_psnr && target_psnr <= 99.0) {
+            return Err(UsageError::new(format!("target-psnr {} is outside 0 (exclusive) to 99", target_psnr)));
+        }
+    }
+    if let Some(filter_strength) = encoder.filter_strength {
+        if 100 < filter_strength {
+            return Err(UsageError::new(format!("filter-strength {} is outside 0 to 100", filter_strength)));
+        }
+    }
+    if let Some(segments) = encoder.segments {
+        if !(1 <= segments && segments <= 4) {
+            return Err(UsageError::new(format!("segments {} is outside 1 to 4", segments)));
+        }
+    }
+    Ok(())
+}
+
+pub fn check_encode_threads(threads: usize) -> Result<(), UsageError> {
+    if !(1 <= threads && threads <= 64) {
+        return Err(UsageError::new(format!("encode-threads {} is outside 1 to 64", threads)));
+    }
+    Ok(())
+}
+
+pub fn check_motion(motion: &MotionConfig) -> Result<(), UsageError> {
+    if !(0.0 <= motion.min_lit_fraction && motion.min_lit_fraction <= 1.0) {
+        return Err(UsageError::new(format!(
+            "min-lit-fraction {} is outside 0 to 1", motion.min_lit_fraction)));
+    }
+    if !(0.0 < motion.learning_rate && motion.learning_rate <= 1.0) {
+        return Err(UsageError::new(format!(
+            "learning-rate {} is outside 0 (exclusive) to 1", motion.learning_rate)));
+    }
+    if !(0.0 <= motion.track_max_distance) {
+        return Err(UsageError::new(format!(
+            "track-max-distance {} must not be negative", motion.track_max_distance)));
+    }
+    if let Some(adaptive) = motion.adaptive_threshold {
+        if adaptive.max < adaptive.min {
+            return Err(UsageError::new(format!(
+                "adaptive threshold range {},{} is empty", adaptive.min, adaptive.max)));
+        }
+        if !(0.0 < adaptive.percentile && adaptive.percentile <= 1.0) {
+            return Err(UsageError::new(format!(
+                "adaptive threshold percentile {} is outside 0 (exclusive) to 1", adaptive.percentile)));
+        }
+    }
+    if let Some(tamper) = motion.tamper {
+        if !(0.0 < tamper.energy_drop && tamper.energy_drop < 1.0) {
+            return Err(UsageError::new(format!(
+                "tamper energy drop {} is outside 0 to 1 (exclusive)", tamper.energy_drop)));
+        }
+        if !(0.0 < tamper.change_fraction && tamper.change_fraction <= 1.0) {
+            return Err(UsageError::new(format!(
+                "tamper change fraction {} is outside 0 (exclusive) to 1", tamper.change_fraction)));
+        }
+    }
+    if let Some(levels) = motion.illumination_threshold {
+        if !(0.0 < levels && levels <= 255.0) {
+            return Err(UsageError::new(format!(
+                "illumination-threshold {} is outside 0 to 255", levels)));
+        }
+    }
+    Ok(())
+}
+
+fn parse_source(matches: &getopts::Matches, positional_device: Option<String>, default: &SourceKind)
+    -> Result<SourceKind, UsageError>
+{
+    let mut sources = Vec::new();
+    if let Some(device) = matches.opt_str("device") {
+        sources.push(SourceKind::Device(device));
+    }
+    if let Some(device) = positional_device {
+        sources.push(SourceKind::Device(device));
+    }
+    if let Some(path) = matches.opt_str("replay") {
+        sources.push(SourceKind::Replay(path));
+    }
+    if matches.opt_present("pattern") {
+        sources.push(SourceKind::Pattern);
+    }
+
+    match sources.len() {
+        0 => Ok(default.clone()),
+        1 => Ok(sources.pop().unwrap()),
+        _ => Err(UsageError::new("choose only one of DEVICE, --device, --replay or --pattern")),
+    }
+}
+
+pub fn parse_resolution(val: &str) -> Result<(u32, u32), UsageError> {
+    let invalid = || UsageError::new(format!("invalid resolution {:?}, expected WIDTHxHEIGHT", val));
+
+    let mut parts = val.splitn(2, 'x');
+    let width: u32 = try!(parts.next().and_then(|v| v.parse().ok()).ok_or_else(&invalid));
+    let height: u32 = try!(parts.next().and_then(|v| v.parse().ok()).ok_or_else(&invalid));
+
+    // YUYV pairs pixels horizontally and 4:2:0 pairs rows.
+    if width == 0 || height == 0 || width % 2 != 0 || height % 2 != 0 {
+        return Err(UsageError::new(format!(
+            "resolution {} must have non-zero, even width and height", val)));
+    }
+    Ok((width, height))
+}
+
+/// Parses `RATE` or `NUM/DEN` frames per second into a V4L2 frame interval.
+pub fn parse_fps(val: &str) -> Result<(u32, u32), UsageError> {
+    let invalid = || UsageError::new(format!("invalid frame rate {:?}, expected RATE or NUM/DEN", val));
+
+    let mut parts = val.splitn(2, '/');
+    let num: u32 = try!(parts.next().and_then(|v| v.parse().ok()).ok_or_else(&invalid));
+    let den: u32 = match parts.next() {
+        Some(v) => try!(v.parse().ok().ok_or_else(&invalid)),
+        None => 1,
+    };
+    if num == 0 || den == 0 {
+        return Err(invalid());
+    }
+    Ok((den, num))
+}
+
+pub fn parse_fourcc(val: &str) -> Result<[u8; 4], UsageError> {
+    let bytes = val.as_bytes();
+    if bytes.len() != 4 || !bytes.iter().all(|b| b.is_ascii_alphanumeric()) {
+        return Err(UsageError::new(format!("invalid pixel format {:?}, expected a FOURCC like YUYV", val)));
+    }
+    let mut fourcc = [0; 4];
+    fourcc.copy_from_slice(bytes);
+    fourcc.make_ascii_uppercase();
+
+    // Everything downstream of capture consumes interleaved YUYV.
+    if &fourcc != b"YUYV" {
+        return Err(UsageError::new(format!("unsupported pixel format {}, only YUYV is supported", val)));
+    }
+    Ok(fourcc)
+}
+
+/// Checks a zone or tripwire coordinate. Points may lie outside the frame,
+/// but not so far out that the geometry done with them overflows.
+pub fn check_coordinate(name: &str, v: f64) -> Result<f32, UsageError> {
+    if v.is_finite() && v.abs() <= MAX_COORDINATE {
+        Ok(v as f32)
+    } else {
+        Err(UsageError::new(format!("{} coordinate {} is outside -{max} to {max}", name, v, max = MAX_COORDINATE)))
+    }
+}
+
+/// Parses a non-negative, possibly fractional, number of seconds.
+pub fn parse_seconds(name: &str, val: &str) -> Result<time::Duration, UsageError> {
+    match val.parse::<f64>() {
+        Ok(secs) if 0.0 <= secs && secs.is_finite() => {
+            Ok(time::Duration::nanoseconds((secs * 1e9) as i64))
+        }
+        _ => Err(UsageError::new(format!("invalid duration {:?} for {}, expected seconds", val, name))),
+    }
+}
+
+fn parse_optional<T: FromStr>(name: &str, matches: &getopts::Matches) -> Result<Option<T>, UsageError> {
+    match matches.opt_str(name) {
+        Some(val) => val.parse().map(Some).map_err(|_| {
+            UsageError::new(format!("invalid value {:?} for --{}", val, name))
+        }),
+        None => Ok(None),
+    }
+}
+
+fn parse_number<T: FromStr>(name: &str, matches: &getopts::Matches, default: T) -> Result<T, UsageError> {
+    match matches.opt_str(name) {
+        Some(val) => val.parse().map_err(|_| {
+            UsageError::new(format!("invalid value {:?} for --{}", val, name))
+        }),
+        None => Ok(default),
+    }
+}
@@ -0,0 +1,607 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+
+use time::Duration;
+use toml;
+
+use cli;
+use mask::{self, Zone, ZoneShape};
+use motion::MotionConfig;
+use tamper::TamperConfig;
+use threshold::AdaptiveThreshold;
+use tripwire::Tripwire;
+use webp::EncoderConfig;
+use pipeline::{PipelineConfig, SourceKind, Streams};
+use source::Pacing;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref err) => write!(f, "{}", err),
+            ConfigError::Parse(ref msg) => write!(f, "{}", msg),
+            ConfigError::Invalid(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> ConfigError {
+        ConfigError::Io(err)
+    }
+}
+
+/// Loads one `PipelineConfig` per `[[camera]]` table.
+///
+/// ```toml
+/// [[camera]]
+/// name = "porch"
+/// device = "/dev/video0"      # or `replay = "FILE"`, or `pattern = true`
+/// width = 1280
+/// height = 960
+/// fps = 5                     # or a fraction, "30000/1001"
+/// output_prefix = "/srv/camcap/porch"
+/// streams = ["fullsize", "edge"]   # also "raw", "heatmap"
+/// heatmap_interval = 300      # seconds between heatmap rewrites
+/// encode_threads = 2          # WebP encoding workers
+///
+/// [camera.encoder]            # all optional; unset ones follow the preset
+/// preset = "picture"          # default, picture, photo, drawing, icon, text
+/// quality = 70
+/// method = 4                  # 0 (fast) to 6 (small)
+/// lossless = false
+/// target_size = 100000        # bytes per frame, instead of quality
+/// target_psnr = 42.0          # dB, instead of quality
+/// filter_strength = 60
+/// segments = 4
+///
+/// [camera.motion]
+/// smoothing = "gaussian:1.5"  # or "none", "average3x3" (default), "box:RADIUS"
+/// edge_operator = "scharr"    # or "sobel" (default), "laplacian"
+/// edge_threshold = 96
+/// morphology = ["open", "close:disk:2"]  # OP[:square|cross|disk[:RADIUS]]
+/// min_lit_fraction = 0.0001
+/// pre_roll = 2.0             # seconds
+/// post_roll = 2.0
+/// detector = "edge-diff"      # or "running-average", "gaussian-mixture"
+/// learning_rate = 0.05        # background model update rate
+/// min_blob_area = 20          # pixels; smaller specks are ignored
+/// min_track_frames = 3        # frames a moving region must persist
+/// track_max_distance = 64.0   # pixels a tracked region may jump per frame
+/// track_max_missed = 2        # frames a track survives unseen
+/// illumination_threshold = 20 # mean luma shift taken as lighting; 0 = never
+///
+/// [camera.motion.adaptive_threshold]  # replaces edge_threshold when present
+/// percentile = 0.95           # fraction of pixels assumed to be noise
+/// margin = 32                 # added to the noise floor
+/// min = 48
+/// max = 192
+///
+/// [camera.motion.tamper]      # report the camera being covered or moved
+/// hold = 10                   # seconds either must last
+/// energy_drop = 0.3           # edge energy below this share of usual = covered
+/// change_fraction = 0.6       # share of edges moved = repositioned
+/// relearn_after = 600         # seconds covered before that view is the norm
+///
+/// [[camera.zone]]             # only motion inside inclusion zones counts
+/// name = "driveway"
+/// polygon = [[0, 480], [1280, 480], [1280, 960], [0, 960]]
+/// threshold = 80              # defaults to motion.edge_threshold
+///
+/// [[camera.zone]]
+/// name = "trees"
+/// bitmap = "/etc/camcap/trees.pgm"   # non-zero pixels are in the zone
+/// exclude = true
+///
+/// [[camera.tripwire]]         # with any tripwires, only crossings record
+/// name = "door"
+/// from = [600, 200]
+/// to = [600, 700]
+/// direction = "forward"       # "backward", or "both" (the default)
+/// ```
+///
+/// Anything left unset takes the same default as the command line.
+pub fn load(path: &str) -> Result<Vec<PipelineConfig>, ConfigError> {
+    let mut text = String::new();
+    try!(try!(fs::File::open(path)).read_to_string(&mut text));
+
+    let mut parser = toml::Parser::new(&text);
+    let root = match parser.parse() {
+        Some(root) => root,
+        None => {
+            let mut messages = Vec::new();
+            for err in parser.errors.iter() {
+                let (line, col) = parser.to_linecol(err.lo);
+                messages.push(format!("{}:{}:{}: {}", path, line + 1, col + 1, err.desc));
+            }
+            return Err(ConfigError::Parse(messages.join("\n")));
+        }
+    };
+
+    for key in root.keys() {
+        if key != "camera" {
+            return Err(ConfigError::Invalid(format!("{}: unknown top-level key {:?}", path, key)));
+        }
+    }
+
+    let cameras = match root.get("camera") {
+        Some(&toml::Value::Array(ref cameras)) => cameras,
+        Some(_) => return Err(ConfigError::Invalid(format!(
+            "{}: `camera` must be an array of tables, written [[camera]]", path))),
+        None => return Err(ConfigError::Invalid(format!("{}: no [[camera]] entries", path))),
+    };
+
+    let mut configs: Vec<PipelineConfig> = Vec::new();
+    for (index, camera) in cameras.iter().enumerate() {
+        let table = match *camera {
+            toml::Value::Table(ref table) => table,
+            _ => return Err(ConfigError::Invalid(format!(
+                "{}: camera #{} is not a table", path, index))),
+        };
+
+        let config = try!(pipeline_from_table(index, table).map_err(|msg| {
+            ConfigError::Invalid(format!("{}: camera #{}: {}", path, index, msg))
+        }));
+
+        if configs.iter().any(|other| other.name == config.name) {
+            return Err(ConfigError::Invalid(format!(
+                "{}: camera name {:?} is used more than once", path, config.name)));
+        }
+        configs.push(config);
+    }
+
+    Ok(configs)
+}
+
+fn pipeline_from_table(index: usize, table: &toml::Table) -> Result<PipelineConfig, String> {
+    const KEYS: &'static [&'static str] = &["name", "device", "replay", "pattern", "realtime",
+        "width", "height", "fps", "format", "output_prefix", "streams", "heatmap_interval",
+        "keep_shift", "punch_shift", "quality", "encoder", "encode_threads", "motion", "zone",
+        "tripwire"];
+    try!(check_keys(table, KEYS));
+
+    let mut config = PipelineConfig::default();
+    config.name = match table.get("name") {
+        Some(val) => try!(get_str("name", val)).to_string(),
+        None => format!("camera{}", index),
+    };
+
+    let mut sources = Vec::new();
+    if let Some(val) = table.get("device") {
+        sources.push(SourceKind::Device(try!(get_str("device", val)).to_string()));
+    }
+    if let Some(val) = table.get("replay") {
+        sources.push(SourceKind::Replay(try!(get_str("replay", val)).to_string()));
+    }
+    if let Some(val) = table.get("pattern") {
+        if try!(get_bool("pattern", val)) {
+            sources.push(SourceKind::Pattern);
+        }
+    }
+    match sources.len() {
+        0 => (),
+        1 => config.source = sources.pop().unwrap(),
+        _ => return Err("choose only one of device, replay or pattern".to_string()),
+    }
+
+    if let Some(val) = table.get("realtime") {
+        if try!(get_bool("realtime", val)) {
+            config.pacing = Pacing::RealTime;
+        }
+    }
+
+    let width = match table.get("width") {
+        Some(val) => try!(get_u32("width", val)),
+        None => config.width,
+    };
+    let height = match table.get("height") {
+        Some(val) => try!(get_u32("height", val)),
+        None => config.height,
+    };
+    let (width, height) = try!(cli::parse_resolution(&format!("{}x{}", width, height))
+        .map_err(|e| e.to_string()));
+    config.width = width;
+    config.height = height;
+
+    if let Some(val) = table.get("fps") {
+        let fps = match *val {
+            toml::Value::Integer(fps) => fps.to_string(),
+            toml::Value::String(ref fps) => fps.clone(),
+            _ => return Err(format!("`fps` must be an integer or a \"NUM/DEN\" string, not {}",
+                val.type_str())),
+        };
+        config.interval = try!(cli::parse_fps(&fps).map_err(|e| e.to_string()));
+    }
+
+    if let Some(val) = table.get("format") {
+        config.fourcc = try!(cli::parse_fourcc(try!(get_str("format", val)))
+            .map_err(|e| e.to_string()));
+    }
+
+    config.prefix = match table.get("output_prefix") {
+        Some(val) => try!(get_str("output_prefix", val)).to_string(),
+        None => return Err("missing `output_prefix`".to_string()),
+    };
+
+    if let Some(val) = table.get("streams") {
+        let names = match *val {
+            toml::Value::Array(ref names) => names,
+            _ => return Err(format!("`streams` must be an array, not {}", val.type_str())),
+        };
+        let mut strs = Vec::new();
+        for name in names.iter() {
+            strs.push(try!(get_str("streams", name)));
+        }
+        config.streams = try!(Streams::parse_list(strs));
+    }
+    if let Some(val) = table.get("heatmap_interval") {
+        config.heatmap_interval = try!(get_seconds("heatmap_interval", val));
+    }
+
+    if let Some(val) = table.get("keep_shift") {
+        config.keep_shift = try!(get_u8("keep_shift", val));
+    }
+    if let Some(val) = table.get("punch_shift") {
+        config.punch_shift = try!(get_u8("punch_shift", val));
+    }
+    try!(cli::check_shifts(config.keep_shift, config.punch_shift).map_err(|e| e.to_string()));
+
+    if let Some(val) = table.get("quality") {
+        config.encoder.quality = try!(get_f64("quality", val)) as f32;
+    }
+    if let Some(val) = table.get("encoder") {
+        if table.contains_key("quality") {
+            return Err("set `quality` in [camera.encoder], not alongside it".to_string());
+        }
+        match *val {
+            toml::Value::Table(ref encoder) => {
+                config.encoder = try!(encoder_from_table(encoder));
+            }
+            _ => return Err(format!("`encoder` must be a table, not {}", val.type_str())),
+        }
+    }
+    try!(cli::check_encoder(&config.encoder).map_err(|e| e.to_string()));
+    if let Some(val) = table.get("encode_threads") {
+        config.encode_threads = try!(get_u32("encode_threads", val)) as usize;
+    }
+    try!(cli::check_encode_threads(config.encode_threads).map_err(|e| e.to_string()));
+
+    if let Some(val) = table.get("motion") {
+        match *val {
+            toml::Value::Table(ref motion) => {
+                config.motion = try!(motion_from_table(motion));
+            }
+            _ => return Err(format!("`motion` must be a table, not {}", val.type_str())),
+        }
+    }
+
+    if let Some(val) = table.get("zone") {
+        let zones = match *val {
+            toml::Value::Array(ref zones) => zones,
+            _ => return Err("`zone` must be an array of tables, written [[camera.zone]]".to_string()),
+        };
+        for (index, zone) in zones.iter().enumerate() {
+            let zone = try!(match *zone {
+                toml::Value::Table(ref zone) => zone_from_table(index, zone, width, height),
+                _ => Err("`zone` must be an array of tables, written [[camera.zone]]".to_string()),
+            });
+            config.motion.zones.push(zone);
+        }
+    }
+
+    if let Some(val) = table.get("tripwire") {
+        let tripwires = match *val {
+            toml::Value::Array(ref tripwires) => tripwires,
+            _ => return Err("`tripwire` must be an array of tables, written [[camera.tripwire]]".to_string()),
+        };
+        for (index, tripwire) in tripwires.iter().enumerate() {
+            let tripwire = try!(match *tripwire {
+                toml::Value::Table(ref tripwire) => tripwire_from_table(index, tripwire),
+                _ => Err("`tripwire` must be an array of tables, written [[camera.tripwire]]".to_string()),
+            });
+            config.motion.tripwires.push(tripwire);
+        }
+    }
+
+    Ok(config)
+}
+
+fn tripwire_from_table(index: usize, table: &toml::Table) -> Result<Tripwire, String> {
+    try!(check_keys(table, &["name", "from", "to", "direction"]));
+
+    let name = match table.get("name") {
+        Some(val) => try!(get_str("tripwire.name", val)).to_string(),
+        None => format!("tripwire{}", index),
+    };
+
+    let mut ends = [(0.0, 0.0); 2];
+    for (end, key) in ends.iter_mut().zip(["from", "to"].iter()) {
+        let pair = match table.get(*key).and_then(|val| val.as_slice()) {
+            Some(pair) if pair.len() == 2 => pair,
+            _ => return Err(format!("tripwire {:?}: `{}` must be an [x, y] pair", name, key)),
+        };
+        let x = try!(get_coordinate("tripwire.point", &pair[0]));
+        let y = try!(get_coordinate("tripwire.point", &pair[1]));
+        *end = (x, y);
+    }
+
+    let direction = match table.get("direction") {
+        Some(val) => match try!(get_str("tripwire.direction", val)) {
+            "both" => None,
+            dir => Some(try!(dir.parse())),
+        },
+        None => None,
+    };
+
+    Ok(Tripwire {
+        name: name,
+        from: ends[0],
+        to: ends[1],
+        direction: direction,
+    })
+}
+
+fn zone_from_table(index: usize, table: &toml::Table, width: u32, height: u32) -> Result<Zone, String> {
+    try!(check_keys(table, &["name", "polygon", "bitmap", "exclude", "threshold"]));
+
+    let name = match table.get("name") {
+        Some(val) => try!(get_str("zone.name", val)).to_string(),
+        None => format!("zone{}", index),
+    };
+
+    let shape = match (table.get("polygon"), table.get("bitmap")) {
+        (Some(val), None) => {
+            let points = match *val {
+                toml::Value::Array(ref points) => points,
+                _ => return Err(format!("zone {:?}: `polygon` must be an array of [x, y] pairs", name)),
+            };
+            let mut vertices = Vec::new();
+            for point in points.iter() {
+                let pair = match point.as_slice() {
+                    Some(pair) if pair.len() == 2 => pair,
+                    _ => return Err(format!("zone {:?}: `polygon` must be an array of [x, y] pairs", name)),
+                };
+                let x = try!(get_coordinate("zone.polygon", &pair[0]));
+                let y = try!(get_coordinate("zone.polygon", &pair[1]));
+                vertices.push((x, y));
+            }
+            if vertices.len() < 3 {
+                return Err(format!("zone {:?}: `polygon` needs at least 3 points", name));
+            }
+            ZoneShape::Polygon(vertices)
+        }
+        (None, Some(val)) => {
+            let path = try!(get_str("zone.bitmap", val));
+            let (bm_width, bm_height, data) = try!(mask::load_pgm(path).map_err(|e| e.to_string()));
+            if (bm_width, bm_height) != (width, height) {
+                return Err(format!("zone {:?}: {} is {}x{}, but the camera captures {}x{}",
+                    name, path, bm_width, bm_height, width, height));
+            }
+            ZoneShape::Bitmap { width: bm_width, height: bm_height, data: data }
+        }
+        _ => return Err(format!("zone {:?}: set exactly one of `polygon` or `bitmap`", name)),
+    };
+
+    let exclude = match table.get("exclude") {
+        Some(val) => try!(get_bool("zone.exclude", val)),
+        None => false,
+    };
+    let threshold = match table.get("threshold") {
+        Some(val) => Some(try!(get_u8("zone.threshold", val))),
+        None => None,
+    };
+    if exclude && threshold.is_some() {
+        return Err(format!("zone {:?}: an excluded zone can't have a threshold", name));
+    }
+
+    Ok(Zone {
+        name: name,
+        shape: shape,
+        exclude: exclude,
+        threshold: threshold,
+    })
+}
+
+fn encoder_from_table(table: &toml::Table) -> Result<EncoderConfig, String> {
+    try!(check_keys(table, &["preset", "quality", "method", "lossless", "target_size",
+        "target_psnr", "filter_strength", "segments"]));
+
+    let mut encoder = EncoderConfig::default();
+    if let Some(val) = table.get("preset") {
+        encoder.preset = try!(try!(get_str("encoder.preset", val)).parse());
+    }
+    if let Some(val) = table.get("quality") {
+        encoder.quality = try!(get_f64("encoder.quality", val)) as f32;
+    }
+    if let Some(val) = table.get("method") {
+        encoder.method = Some(try!(get_u8("encoder.method", val)));
+    }
+    if let Some(val) = table.get("lossless") {
+        encoder.lossless = try!(get_bool("encoder.lossless", val));
+    }
+    if let Some(val) = table.get("target_size") {
+        encoder.target_size = Some(try!(get_u32("encoder.target_size", val)));
+    }
+    if let Some(val) = table.get("target_psnr") {
+        encoder.target_psnr = Some(try!(get_f64("encoder.target_psnr", val)) as f32);
+    }
+    if let Some(val) = table.get("filter_strength") {
+        encoder.filter_strength = Some(try!(get_u8("encoder.filter_strength", val)));
+    }
+    if let Some(val) = table.get("segments") {
+        encoder.segments = Some(try!(get_u8("encoder.segments", val)));
+    }
+    Ok(encoder)
+}
+
+fn motion_from_table(table: &toml::Table) -> Result<MotionConfig, String> {
+    try!(check_keys(table, &["smoothing", "edge_operator", "edge_threshold", "morphology",
+        "adaptive_threshold", "min_lit_fraction", "pre_roll", "post_roll", "detector",
+        "learning_rate", "min_blob_area", "min_track_frames", "track_max_distance",
+        "track_max_missed", "illumination_threshold", "tamper"]));
+
+    let mut motion = MotionConfig::default();
+    if let Some(val) = table.get("smoothing") {
+        motion.smoothing = try!(try!(get_str("motion.smoothing", val)).parse());
+    }
+    if let Some(val) = table.get("edge_operator") {
+        motion.edge_operator = try!(try!(get_str("motion.edge_operator", val)).parse());
+    }
+    if let Some(val) = table.get("edge_threshold") {
+        motion.edge_threshold = try!(get_u8("motion.edge_threshold", val));
+    }
+    if let Some(val) = table.get("morphology") {
+        let steps = match *val {
+            toml::Value::Array(ref steps) => steps,
+            _ => return Err(format!("`motion.morphology` must be an array, not {}", val.type_str())),
+        };
+        for step in steps.iter() {
+            motion.morphology.push(try!(try!(get_str("motion.morphology", step)).parse()));
+        }
+    }
+    if let Some(val) = table.get("adaptive_threshold") {
+        match *val {
+            toml::Value::Table(ref adaptive) => {
+                motion.adaptive_threshold = Some(try!(adaptive_threshold_from_table(adaptive)));
+            }
+            _ => return Err(format!("`motion.adaptive_threshold` must be a table, not {}", val.type_str())),
+        }
+    }
+    if let Some(val) = table.get("tamper") {
+        match *val {
+            toml::Value::Table(ref tamper) => {
+                motion.tamper = Some(try!(tamper_from_table(tamper)));
+            }
+            _ => return Err(format!("`motion.tamper` must be a table, not {}", val.type_str())),
+        }
+    }
+    if let Some(val) = table.get("min_lit_fraction") {
+        motion.min_lit_fraction = try!(get_f64("motion.min_lit_fraction", val));
+    }
+    if let Some(val) = table.get("pre_roll") {
+        motion.pre_roll = try!(get_seconds("motion.pre_roll", val));
+    }
+    if let Some(val) = table.get("post_roll") {
+        motion.post_roll = try!(get_seconds("motion.post_roll", val));
+    }
+    if let Some(val) = table.get("detector") {
+        motion.detector = try!(try!(get_str("motion.detector", val)).parse());
+    }
+    if let Some(val) = table.get("learning_rate") {
+        motion.learning_rate = try!(get_f64("motion.learning_rate", val)) as f32;
+    }
+    if let Some(val) = table.get("min_blob_area") {
+        motion.min_blob_area = try!(get_u32("motion.min_blob_area", val)) as usize;
+    }
+    if let Some(val) = table.get("min_track_frames") {
+        motion.min_track_frames = try!(get_u32("motion.min_track_frames", val));
+    }
+    if let Some(val) = table.get("track_max_distance") {
+        motion.track_max_distance = try!(get_f64("motion.track_max_distance", val)) as f32;
+    }
+    if let Some(val) = table.get("track_max_missed") {
+        motion.track_max_missed = try!(get_u32("motion.track_max_missed", val));
+    }
+    if let Some(val) = table.get("illumination_threshold") {
+        let levels = try!(get_f64("motion.illumination_threshold", val)) as f32;
+        motion.illumination_threshold = if levels == 0.0 { None } else { Some(levels) };
+    }
+    try!(cli::check_motion(&motion).map_err(|e| e.to_string()));
+    Ok(motion)
+}
+
+fn adaptive_threshold_from_table(table: &toml::Table) -> Result<AdaptiveThreshold, String> {
+    try!(check_keys(table, &["percentile", "margin", "min", "max"]));
+
+    let mut adaptive = AdaptiveThreshold::default();
+    if let Some(val) = table.get("percentile") {
+        adaptive.percentile = try!(get_f64("adaptive_threshold.percentile", val)) as f32;
+    }
+    if let Some(val) = table.get("margin") {
+        adaptive.margin = try!(get_u8("adaptive_threshold.margin", val));
+    }
+    if let Some(val) = table.get("min") {
+        adaptive.min = try!(get_u8("adaptive_threshold.min", val));
+    }
+    if let Some(val) = table.get("max") {
+        adaptive.max = try!(get_u8("adaptive_threshold.max", val));
+    }
+    Ok(adaptive)
+}
+
+fn tamper_from_table(table: &toml::Table) -> Result<TamperConfig, String> {
+    try!(check_keys(table, &["hold", "energy_drop", "change_fraction", "relearn_after"]));
+
+    let mut tamper = TamperConfig::default();
+    if let Some(val) = table.get("hold") {
+        tamper.hold = try!(get_seconds("tamper.hold", val));
+    }
+    if let Some(val) = table.get("energy_drop") {
+        tamper.energy_drop = try!(get_f64("tamper.energy_drop", val)) as f32;
+    }
+    if let Some(val) = table.get("change_fraction") {
+        tamper.change_fraction = try!(get_f64("tamper.change_fraction", val)) as f32;
+    }
+    if let Some(val) = table.get("relearn_after") {
+        tamper.relearn_after = try!(get_seconds("tamper.relearn_after", val));
+    }
+    Ok(tamper)
+}
+
+fn check_keys(table: &toml::Table, known: &[&str]) -> Result<(), String> {
+    for key in table.keys() {
+        if !known.contains(&&key[..]) {
+            return Err(format!("unknown key {:?}", key));
+        }
+    }
+    Ok(())
+}
+
+fn get_str<'a>(key: &str, val: &'a toml::Value) -> Result<&'a str, String> {
+    val.as_str().ok_or_else(|| format!("`{}` must be a string, not {}", key, val.type_str()))
+}
+
+fn get_bool(key: &str, val: &toml::Value) -> Result<bool, String> {
+    val.as_bool().ok_or_else(|| format!("`{}` must be a boolean, not {}", key, val.type_str()))
+}
+
+fn get_f64(key: &str, val: &toml::Value) -> Result<f64, String> {
+    match *val {
+        toml::Value::Float(v) => Ok(v),
+        toml::Value::Integer(v) => Ok(v as f64),
+        _ => Err(format!("`{}` must be a number, not {}", key, val.type_str())),
+    }
+}
+
+fn get_coordinate(key: &str, val: &toml::Value) -> Result<f32, String> {
+    let v = try!(get_f64(key, val));
+    cli::check_coordinate(&format!("`{}`", key), v).map_err(|e| e.to_string())
+}
+
+fn get_seconds(key: &str, val: &toml::Value) -> Result<Duration, String> {
+    let secs = try!(get_f64(key, val));
+    cli::parse_seconds(key, &secs.to_string()).map_err(|e| e.to_string())
+}
+
+fn get_u32(key: &str, val: &toml::Value) -> Result<u32, String> {
+    match val.as_integer() {
+        Some(v) if 0 <= v && v <= ::std::u32::MAX as i64 => Ok(v as u32),
+        Some(v) => Err(format!("`{}` is out of range: {}", key, v)),
+        None => Err(format!("`{}` must be an integer, not {}", key, val.type_str())),
+    }
+}
+
+fn get_u8(key: &str, val: &toml::Value) -> Result<u8, String> {
+    match val.as_integer() {
+        Some(v) if 0 <= v && v <= ::std::u8::MAX as i64 => Ok(v as u8),
+        Some(v) => Err(format!("`{}` is out of range: {}", key, v)),
+        None => Err(format!("`{}` must be an integer, not {}", key, val.type_str())),
+    }
+}
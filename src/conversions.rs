@@ -44,6 +44,30 @@ pub fn yuyv_interleave_to_yuv422p<S1, S2>(
     assert_eq!(subpixel_ctr, from.raw_bytes().len());
 }
 
+/// planar -> interleaved
+pub fn yuv422p_to_yuyv_interleave<S1, S2>(
+    from: &Surface<Yuv422p, u8, S1>,
+    into: &mut Surface<Yuv422, u8, S2>,
+)
+    where
+        S1: Deref<Target=[u8]>,
+        S2: Deref<Target=[u8]> + DerefMut,
+{
+    assert_eq!(from.width(), into.width());
+    assert_eq!(from.height(), into.height());
+
+    let (yp, up, vp) = from.get_planes();
+    let mut dst = into.raw_bytes_mut().chunks_mut(4);
+
+    for ((ys, us), vs) in yp.chunks(2).zip(up.iter()).zip(vp.iter()) {
+        let quad = dst.next().unwrap();
+        quad[0] = ys[0];
+        quad[1] = *us;
+        quad[2] = ys[1];
+        quad[3] = *vs;
+    }
+}
+
 pub fn yuv422p_from_buffer_mut(px_count: usize, buffer: &mut [u8])
 -> (&mut [u8], &mut [u8], &mut [u8])
 {
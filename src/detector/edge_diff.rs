@@ -0,0 +1,40 @@
+use surface::{Surface, Luma};
+
+use compose::{compose, ComposeMode};
+use super::{MotionDetector, DetectorInput};
+
+/// Compares each frame's edges against the previous frame's. Cheap and
+/// insensitive to gradual lighting changes, but anything moving slowly
+/// enough to look the same in consecutive frames is missed.
+pub struct EdgeDiffDetector {
+    width: u32,
+    height: u32,
+    /// `None` after a reset, until the next frame becomes the baseline.
+    previous_edge: Option<Surface<Luma, u8, Box<[u8]>>>,
+}
+
+impl EdgeDiffDetector {
+    pub fn new(width: u32, height: u32) -> EdgeDiffDetector {
+        EdgeDiffDetector {
+            width: width,
+            height: height,
+            previous_edge: Some(Surface::new_black(width, height)),
+        }
+    }
+}
+
+impl MotionDetector for EdgeDiffDetector {
+    fn detect(&mut self, input: &DetectorInput) -> Surface<Luma, u8, Box<[u8]>> {
+        let diff = match self.previous_edge {
+            Some(ref previous_edge) => compose(previous_edge, input.edge, ComposeMode::AbsoluteDiff),
+            None => Surface::new_black(self.width, self.height),
+        };
+        self.previous_edge = Some(Surface::new(input.edge.width(), input.edge.height(),
+            input.edge.raw_bytes().to_vec().into_boxed_slice()));
+        diff
+    }
+
+    fn reset(&mut self) {
+        self.previous_edge = None;
+    }
+}
@@ -0,0 +1,129 @@
+use surface::{Surface, Luma};
+
+use super::{MotionDetector, DetectorInput};
+
+const COMPONENTS: usize = 3;
+
+// A sample within this many standard deviations matches a component.
+const MATCH_SIGMAS: f32 = 2.5;
+
+// Components are background, most reliable first, until their weights add
+// up to this.
+const BACKGROUND_WEIGHT: f32 = 0.7;
+
+const INITIAL_VARIANCE: f32 = 15.0 * 15.0;
+const MIN_VARIANCE: f32 = 4.0 * 4.0;
+const INITIAL_WEIGHT: f32 = 0.05;
+
+#[derive(Clone, Copy)]
+struct Component {
+    weight: f32,
+    mean: f32,
+    variance: f32,
+}
+
+/// Stauffer-Grimson background model: every pixel is a small mixture of
+/// Gaussians over luma, which copes with backgrounds that flicker between
+/// a few values (foliage, water, monitors). Pixels that fit none of the
+/// background components are reported as 0xFF, all others as 0.
+pub struct GaussianMixtureDetector {
+    width: u32,
+    height: u32,
+    rate: f32,
+    // COMPONENTS entries per pixel, kept sorted by reliability.
+    model: Option<Vec<Component>>,
+}
+
+impl GaussianMixtureDetector {
+    pub fn new(width: u32, height: u32, rate: f32) -> GaussianMixtureDetector {
+        GaussianMixtureDetector {
+            width: width,
+            height: height,
+            rate: rate,
+            model: None,
+        }
+    }
+}
+
+impl MotionDetector for GaussianMixtureDetector {
+    fn detect(&mut self, input: &DetectorInput) -> Surface<Luma, u8, Box<[u8]>> {
+        let mut out = Surface::new_black(self.width, self.height);
+
+        let luma = input.luma.raw_bytes();
+        let model = match self.model {
+            Some(ref mut model) => model,
+            None => {
+                let mut model = Vec::with_capacity(luma.len() * COMPONENTS);
+                for px in luma.iter() {
+                    model.push(Component { weight: 1.0, mean: *px as f32, variance: INITIAL_VARIANCE });
+                    for _ in 1..COMPONENTS {
+                        model.push(Component { weight: 0.0, mean: 0.0, variance: INITIAL_VARIANCE });
+                    }
+                }
+                self.model = Some(model);
+                return out;
+            }
+        };
+
+        let rate = self.rate;
+        for ((mix, px), o) in model.chunks_mut(COMPONENTS).zip(luma.iter()).zip(out.raw_bytes_mut().iter_mut()) {
+            let px = *px as f32;
+            if !update_pixel(mix, px, rate) {
+                *o = 0xFF;
+            }
+        }
+        out
+    }
+
+    fn reset(&mut self) {
+        self.model = None;
+    }
+}
+
+// Updates one pixel's mixture with sample `px`, returning whether it was
+// explained by a background component.
+fn update_pixel(mix: &mut [Component], px: f32, rate: f32) -> bool {
+    let matched = mix.iter().position(|c| {
+        let dist = px - c.mean;
+        0.0 < c.weight && dist * dist < MATCH_SIGMAS * MATCH_SIGMAS * c.variance
+    });
+
+    // whether the match was background is decided on the model as it was
+    // before this sample: the more reliable components ahead of it must
+    // not already account for BACKGROUND_WEIGHT.
+    let is_background = match matched {
+        Some(idx) => mix[..idx].iter().map(|c| c.weight).sum::<f32>() <= BACKGROUND_WEIGHT,
+        None => false,
+    };
+
+    for (i, c) in mix.iter_mut().enumerate() {
+        let owned = if Some(i) == matched { 1.0 } else { 0.0 };
+        c.weight += rate * (owned - c.weight);
+    }
+
+    match matched {
+        Some(idx) => {
+            let c = &mut mix[idx];
+            let dist = px - c.mean;
+            c.mean += rate * dist;
+            c.variance = (c.variance + rate * (dist * dist - c.variance)).max(MIN_VARIANCE);
+        }
+        None => {
+            // replace the least reliable component with one centred here.
+            let last = mix.len() - 1;
+            mix[last] = Component { weight: INITIAL_WEIGHT, mean: px, variance: INITIAL_VARIANCE };
+        }
+    }
+
+    let total: f32 = mix.iter().map(|c| c.weight).sum();
+    for c in mix.iter_mut() {
+        c.weight /= total;
+    }
+    mix.sort_by(|a, b| {
+        let ra = a.weight / a.variance.sqrt();
+        let rb = b.weight / b.variance.sqrt();
+        rb.partial_cmp(&ra).unwrap()
+    });
+
+    is_background
+}
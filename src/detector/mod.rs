@@ -0,0 +1,82 @@
+use std::fmt;
+use std::str::FromStr;
+
+use surface::{Surface, Luma};
+
+mod edge_diff;
+mod running_average;
+mod gaussian_mixture;
+
+pub use self::edge_diff::EdgeDiffDetector;
+pub use self::running_average::RunningAverageDetector;
+pub use self::gaussian_mixture::GaussianMixtureDetector;
+
+/// What the motion stage has computed for a frame before detection.
+pub struct DetectorInput<'a> {
+    /// The frame's luma after `MotionConfig::smoothing`.
+    pub luma: &'a Surface<Luma, u8, Box<[u8]>>,
+    /// Edge magnitude of `luma`, from `MotionConfig::edge_operator`.
+    pub edge: &'a Surface<Luma, u8, Box<[u8]>>,
+}
+
+/// Turns each frame into a foreground map, where larger values mean the
+/// pixel is more likely to belong to something moving. The map is then
+/// masked and thresholded by `MotionContext`.
+pub trait MotionDetector: Send {
+    fn detect(&mut self, input: &DetectorInput) -> Surface<Luma, u8, Box<[u8]>>;
+
+    /// Forgets everything learned so far; the next frame becomes the new
+    /// baseline.
+    fn reset(&mut self);
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DetectorKind {
+    /// Difference between consecutive edge maps.
+    EdgeDiff,
+    /// Difference from an exponential running average of luma.
+    RunningAverage,
+    /// Per-pixel mixture of Gaussians over luma; foreground pixels are 0xFF.
+    GaussianMixture,
+}
+
+impl DetectorKind {
+    /// `learning_rate` is the weight each new frame gets in a background
+    /// model; the edge-diff detector has no model and ignores it.
+    pub fn build(&self, width: u32, height: u32, learning_rate: f32) -> Box<MotionDetector> {
+        match *self {
+            DetectorKind::EdgeDiff => Box::new(EdgeDiffDetector::new(width, height)),
+            DetectorKind::RunningAverage => {
+                Box::new(RunningAverageDetector::new(width, height, learning_rate))
+            }
+            DetectorKind::GaussianMixture => {
+                Box::new(GaussianMixtureDetector::new(width, height, learning_rate))
+            }
+        }
+    }
+}
+
+impl FromStr for DetectorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DetectorKind, String> {
+        match s {
+            "edge-diff" => Ok(DetectorKind::EdgeDiff),
+            "running-average" => Ok(DetectorKind::RunningAverage),
+            "gaussian-mixture" => Ok(DetectorKind::GaussianMixture),
+            _ => Err(format!(
+                "unknown detector {:?}, expected edge-diff, running-average or gaussian-mixture", s)),
+        }
+    }
+}
+
+impl fmt::Display for DetectorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            DetectorKind::EdgeDiff => "edge-diff",
+            DetectorKind::RunningAverage => "running-average",
+            DetectorKind::GaussianMixture => "gaussian-mixture",
+        };
+        write!(f, "{}", name)
+    }
+}
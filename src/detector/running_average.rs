@@ -0,0 +1,51 @@
+use surface::{Surface, Luma};
+
+use super::{MotionDetector, DetectorInput};
+
+/// Keeps an exponential moving average of luma as the background and
+/// reports each pixel's distance from it, so objects that arrive slowly
+/// still stand out until they've been in place for a while.
+pub struct RunningAverageDetector {
+    width: u32,
+    height: u32,
+    rate: f32,
+    background: Option<Vec<f32>>,
+}
+
+impl RunningAverageDetector {
+    pub fn new(width: u32, height: u32, rate: f32) -> RunningAverageDetector {
+        RunningAverageDetector {
+            width: width,
+            height: height,
+            rate: rate,
+            background: None,
+        }
+    }
+}
+
+impl MotionDetector for RunningAverageDetector {
+    fn detect(&mut self, input: &DetectorInput) -> Surface<Luma, u8, Box<[u8]>> {
+        let mut out = Surface::new_black(self.width, self.height);
+
+        let luma = input.luma.raw_bytes();
+        let background = match self.background {
+            Some(ref mut background) => background,
+            None => {
+                self.background = Some(luma.iter().map(|px| *px as f32).collect());
+                return out;
+            }
+        };
+
+        let rate = self.rate;
+        for ((bg, px), o) in background.iter_mut().zip(luma.iter()).zip(out.raw_bytes_mut().iter_mut()) {
+            let px = *px as f32;
+            *o = (px - *bg).abs().min(255.0) as u8;
+            *bg += rate * (px - *bg);
+        }
+        out
+    }
+
+    fn reset(&mut self) {
+        self.background = None;
+    }
+}
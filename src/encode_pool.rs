@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use time::{self, Duration};
+use surface::{Surface, Yuv420p};
+
+use webp::{EncodeError, EncoderConfig, WebpEncoder};
+
+/// A finished frame, handed back in the order it was submitted.
+pub struct Encoded<T> {
+    pub meta: T,
+    pub webp: Result<Vec<u8>, EncodeError>,
+    /// Time spent in the encoder itself, not waiting in the queue.
+    pub elapsed: Duration,
+}
+
+struct Job<T> {
+    seq: u64,
+    meta: T,
+    surface: Surface<Yuv420p, u8, Box<[u8]>>,
+    /// Swapped with the encoder's output to carry the frame back.
+    buffer: Vec<u8>,
+}
+
+/// Encodes WebP frames on a set of worker threads, each with its own
+/// `WebpEncoder`.
+///
+/// Frames may finish out of order; `finished` and `finish` hold them back
+/// until every earlier frame is done. `meta` rides along with each frame
+/// untouched. Output buffers handed back with `recycle` are reused for
+/// later frames.
+pub struct EncodePool<T> {
+    jobs: Option<SyncSender<Job<T>>>,
+    results: Receiver<(u64, Encoded<T>)>,
+    workers: Vec<JoinHandle<()>>,
+    next_seq: u64,
+    next_out: u64,
+    pending: BTreeMap<u64, Encoded<T>>,
+    spare: Vec<Vec<u8>>,
+    max_spare: usize,
+}
+
+impl<T: Send + 'static> EncodePool<T> {
+    pub fn new(name: &str, threads: usize, config: EncoderConfig) -> io::Result<EncodePool<T>> {
+        // at most a couple of frames queued per worker, so a backlog stalls
+        // the caller rather than piling up frames in memory.
+        let (jobs_tx, jobs_rx) = sync_channel(2 * threads);
+        let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+        let (results_tx, results_rx) = channel();
+
+        let mut workers = Vec::with_capacity(threads);
+        for index in 0..threads {
+            let encoder = try!(WebpEncoder::new(config)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string())));
+            let jobs_rx = jobs_rx.clone();
+            let results_tx = results_tx.clone();
+            workers.push(try!(thread::Builder::new()
+                .name(format!("{}-encode{}", name, index))
+                .spawn(move || work(encoder, jobs_rx, results_tx))));
+        }
+
+        Ok(EncodePool {
+            jobs: Some(jobs_tx),
+            results: results_rx,
+            workers: workers,
+            next_seq: 0,
+            next_out: 0,
+            pending: BTreeMap::new(),
+            // enough for every frame that can be queued or in a worker.
+            spare: Vec::new(),
+            max_spare: 3 * threads,
+        })
+    }
+
+    /// Queues a frame, blocking while every worker is busy and the queue is
+    /// full.
+    pub fn submit(&mut self, meta: T, surface: Surface<Yuv420p, u8, Box<[u8]>>) -> io::Result<()> {
+        let job = Job {
+            seq: self.next_seq,
+            meta: meta,
+            surface: surface,
+            buffer: self.spare.pop().unwrap_or_else(Vec::new),
+        };
+        self.next_seq += 1;
+
+        let jobs = match self.jobs {
+            Some(ref jobs) => jobs,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "encode pool already finished")),
+        };
+        jobs.send(job).map_err(|_| io::Error::new(io::ErrorKind::Other, "encode workers have exited"))
+    }
+
+    /// Hands back a finished frame's buffer for a later frame to use.
+    pub fn recycle(&mut self, buffer: Vec<u8>) {
+        if self.spare.len() < self.max_spare {
+            self.spare.push(buffer);
+        }
+    }
+
+    /// The frames that are done, oldest first, stopping at the first one
+    /// that isn't.
+    pub fn finished(&mut self) -> Vec<Encoded<T>> {
+        while let Ok((seq, encoded)) = self.results.try_recv() {
+            self.pending.insert(seq, encoded);
+        }
+        self.take_ready()
+    }
+
+    /// Waits for every queued frame and returns the rest of them in order.
+    pub fn finish(&mut self) -> io::Result<Vec<Encoded<T>>> {
+        self.jobs = None;
+        let mut panicked = false;
+        for worker in self.workers.drain(..) {
+            panicked |= worker.join().is_err();
+        }
+        if panicked {
+            return Err(io::Error::new(io::ErrorKind::Other, "an encode worker panicked"));
+        }
+
+        while let Ok((seq, encoded)) = self.results.try_recv() {
+            self.pending.insert(seq, encoded);
+        }
+        Ok(self.take_ready())
+    }
+
+    fn take_ready(&mut self) -> Vec<Encoded<T>> {
+        let mut ready = Vec::new();
+        while let Some(encoded) = self.pending.remove(&self.next_out) {
+            ready.push(encoded);
+            self.next_out += 1;
+        }
+        ready
+    }
+}
+
+fn work<T>(mut encoder: WebpEncoder, jobs: Arc<Mutex<Receiver<Job<T>>>>, results: Sender<(u64, Encoded<T>)>) {
+    loop {
+        // hold the lock only while taking a job, not while encoding it.
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+
+        let start = time::get_time();
+        let result = encoder.encode(&job.surface).map(|_| ());
+        let webp = match result {
+            Ok(()) => Ok(encoder.swap_output(job.buffer)),
+            Err(err) => Err(err),
+        };
+        let encoded = Encoded {
+            meta: job.meta,
+            webp: webp,
+            elapsed: time::get_time() - start,
+        };
+        if results.send((job.seq, encoded)).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,446 @@
+use std::io::{self, Read, Write};
+use std::fmt;
+use std::error::Error;
+
+use time::Timespec;
+use byteorder::{ByteOrder, WriteBytesExt, BigEndian, LittleEndian};
+
+pub const PREAMBLE: &'static [u8] = &[0x98, 0x56, 0xcb, 0x6b, 0x56, 0xf8, 0xc8, 0x15];
+
+/// Marks a frame that carries the id of the motion event it belongs to.
+pub const PREAMBLE_EVENT: &'static [u8] = &[0x98, 0x56, 0xcb, 0x6b, 0x56, 0xf8, 0xc8, 0x16];
+
+// PREAMBLE + i64 seconds + i32 nanoseconds + u32 payload length
+const HEADER_LEN: usize = 8 + 8 + 4 + 4;
+
+// PREAMBLE_EVENT + i64 seconds + i32 nanoseconds + u64 event id + u32 payload length
+const EVENT_HEADER_LEN: usize = 8 + 8 + 4 + 8 + 4;
+
+// RIFF + u32 chunk size + WEBP
+const RIFF_HEADER_LEN: usize = 12;
+
+// Anything claiming to be larger than this is a corrupt header, not a frame.
+const MAX_PAYLOAD_LEN: usize = 64 << 20;
+
+const READ_CHUNK: usize = 64 << 10;
+
+pub struct FwebpFrame {
+    pub when: Timespec,
+    /// Set for frames written with `PREAMBLE_EVENT`.
+    pub event_id: Option<u64>,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum FwebpError {
+    Io(io::Error),
+    /// The stream ended part way through a frame.
+    Truncated,
+}
+
+impl fmt::Display for FwebpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FwebpError::Io(ref err) => write!(f, "fwebp read failed: {}", err),
+            FwebpError::Truncated => write!(f, "fwebp stream truncated mid-frame"),
+        }
+    }
+}
+
+impl Error for FwebpError {
+    fn description(&self) -> &str {
+        match *self {
+            FwebpError::Io(ref err) => err.description(),
+            FwebpError::Truncated => "fwebp stream truncated mid-frame",
+        }
+    }
+}
+
+impl From<io::Error> for FwebpError {
+    fn from(err: io::Error) -> FwebpError {
+        FwebpError::Io(err)
+    }
+}
+
+pub fn write_frame<W: Write>(wri: &mut W, when: Timespec, event_id: Option<u64>, payload: &[u8])
+    -> io::Result<()>
+{
+    try!(wri.write_all(if event_id.is_some() { PREAMBLE_EVENT } else { PREAMBLE }));
+    try!(wri.write_i64::<BigEndian>(when.sec));
+    try!(wri.write_i32::<BigEndian>(when.nsec));
+    if let Some(event_id) = event_id {
+        try!(wri.write_u64::<BigEndian>(event_id));
+    }
+    try!(wri.write_u32::<BigEndian>(payload.len() as u32));
+    try!(wri.write_all(payload));
+    Ok(())
+}
+
+/// Reads frames back out of a `.fwebp` stream.
+///
+/// Both the plain and event-tagged frame layouts are understood.
+/// Anything that doesn't look like a well-formed frame (bad preamble,
+/// nonsensical timestamp or length, payload that isn't a RIFF/WEBP
+/// container) is skipped by scanning forward for the next `PREAMBLE`,
+/// so a truncated or partly overwritten capture still yields every
+/// intact frame. A frame cut short by fewer bytes than a preamble, and
+/// padded out by the start of one, can't be told from an intact frame.
+pub struct FwebpReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    eof: bool,
+    finished: bool,
+    in_sync: bool,
+
+    skipped_bytes: u64,
+    resync_count: u64,
+}
+
+impl<R: Read> FwebpReader<R> {
+    pub fn new(inner: R) -> FwebpReader<R> {
+        FwebpReader {
+            inner: inner,
+            buf: Vec::new(),
+            pos: 0,
+            eof: false,
+            finished: false,
+            in_sync: true,
+
+            skipped_bytes: 0,
+            resync_count: 0,
+        }
+    }
+
+    /// Number of bytes discarded while looking for a valid frame.
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
+    }
+
+    /// Number of times the reader lost sync and had to scan for a preamble.
+    pub fn resync_count(&self) -> u64 {
+        self.resync_count
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn available(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    // Ensures at least `want` unconsumed bytes are buffered. Returns false
+    // if the underlying reader hit EOF first.
+    fn fill_to(&mut self, want: usize) -> io::Result<bool> {
+        if self.pos > READ_CHUNK && self.pos * 2 > self.buf.len() {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+
+        while !self.eof && self.available() < want {
+            let old_len = self.buf.len();
+            self.buf.resize(old_len + READ_CHUNK, 0);
+            match self.inner.read(&mut self.buf[old_len..]) {
+                Ok(0) => {
+                    self.buf.truncate(old_len);
+                    self.eof = true;
+                }
+                Ok(length) => self.buf.truncate(old_len + length),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {
+                    self.buf.truncate(old_len);
+                }
+                Err(err) => {
+                    self.buf.truncate(old_len);
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(want <= self.available())
+    }
+
+    fn skip(&mut self, count: usize) {
+        if self.in_sync {
+            self.in_sync = false;
+            self.resync_count += 1;
+        }
+        self.pos += count;
+        self.skipped_bytes += count as u64;
+    }
+
+    // Moves `pos` to the next preamble candidate. If none is buffered, all
+    // but the last `PREAMBLE.len() - 1` bytes are discarded, since those
+    // may be the start of a preamble split across reads.
+    fn scan_for_preamble(&mut self) {
+        match find_preamble(&self.buf[self.pos..]) {
+            Some(offset) => self.skip(offset),
+            None => {
+                let keep = ::std::cmp::min(self.available(), PREAMBLE.len() - 1);
+                let discard = self.available() - keep;
+                self.skip(discard);
+            }
+        }
+    }
+
+    fn read_frame(&mut self) -> Result<Option<FwebpFrame>, FwebpError> {
+        loop {
+            if !try!(self.fill_to(PREAMBLE.len())) {
+                // Trailing garbage too short to hold a preamble.
+                let rest = self.available();
+                if 0 < rest {
+                    self.skip(rest);
+                }
+                return Ok(None);
+            }
+
+            let header_len = match preamble_header_len(&self.buf[self.pos..]) {
+                Some(header_len) => header_len,
+                None => {
+                    self.scan_for_preamble();
+                    continue;
+                }
+            };
+
+            if !try!(self.fill_to(header_len)) {
+                if find_preamble(&self.buf[self.pos + 1..]).is_some() {
+                    self.skip(1);
+                    continue;
+                }
+                let rest = self.available();
+                self.skip(rest);
+                return Err(FwebpError::Truncated);
+            }
+
+            let (sec, nsec, event_id, payload_len) = {
+                let header = &self.buf[self.pos..][..header_len];
+                let sec = BigEndian::read_i64(&header[8..16]);
+                let nsec = BigEndian::read_i32(&header[16..20]);
+                let event_id = match header_len {
+                    EVENT_HEADER_LEN => Some(BigEndian::read_u64(&header[20..28])),
+                    _ => None,
+                };
+                let length = BigEndian::read_u32(&header[header_len - 4..]) as usize;
+                (sec, nsec, event_id, length)
+            };
+
+            let header_ok = 0 <= nsec && nsec < 1_000_000_000
+                && RIFF_HEADER_LEN <= payload_len && payload_len <= MAX_PAYLOAD_LEN;
+            if !header_ok {
+                // step past this preamble so the scan finds the next one.
+                self.skip(1);
+                continue;
+            }
+
+            if !try!(self.fill_to(header_len + payload_len)) {
+                // A later preamble inside the claimed payload means this
+                // frame was cut short and something else was written after.
+                if find_preamble(&self.buf[self.pos + 1..]).is_some() {
+                    self.skip(1);
+                    continue;
+                }
+                let rest = self.available();
+                self.skip(rest);
+                return Err(FwebpError::Truncated);
+            }
+
+            // Likewise if the claimed header and payload were filled out by
+            // whatever came next, which shows as a preamble starting
+            // anywhere inside them, even one running past their end.
+            let frame_len = header_len + payload_len;
+            try!(self.fill_to(frame_len + PREAMBLE.len() - 1));
+            let overlap_end = ::std::cmp::min(self.available(), frame_len + PREAMBLE.len() - 1);
+            let frame_ok = is_webp_payload(&self.buf[self.pos + header_len..][..payload_len])
+                && find_preamble(&self.buf[self.pos + 1..self.pos + overlap_end]).is_none();
+            if !frame_ok {
+                self.skip(1);
+                continue;
+            }
+
+            let payload = self.buf[self.pos + header_len..][..payload_len].to_vec();
+            self.pos += header_len + payload_len;
+            self.in_sync = true;
+
+            return Ok(Some(FwebpFrame {
+                when: Timespec::new(sec, nsec),
+                event_id: event_id,
+                payload: payload,
+            }));
+        }
+    }
+}
+
+impl<R: Read> Iterator for FwebpReader<R> {
+    type Item = Result<FwebpFrame, FwebpError>;
+
+    fn next(&mut self) -> Option<Result<FwebpFrame, FwebpError>> {
+        if self.finished {
+            return None;
+        }
+        match self.read_frame() {
+            Ok(Some(frame)) => Some(Ok(frame)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(err) => {
+                self.finished = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+fn preamble_header_len(buf: &[u8]) -> Option<usize> {
+    if buf.starts_with(PREAMBLE) {
+        Some(HEADER_LEN)
+    } else if buf.starts_with(PREAMBLE_EVENT) {
+        Some(EVENT_HEADER_LEN)
+    } else {
+        None
+    }
+}
+
+fn find_preamble(buf: &[u8]) -> Option<usize> {
+    buf.windows(PREAMBLE.len()).position(|w| w == PREAMBLE || w == PREAMBLE_EVENT)
+}
+
+fn is_webp_payload(payload: &[u8]) -> bool {
+    if payload.len() < RIFF_HEADER_LEN {
+        return false;
+    }
+    if &payload[0..4] != b"RIFF" || &payload[8..12] != b"WEBP" {
+        return false;
+    }
+    let riff_len = LittleEndian::read_u32(&payload[4..8]) as usize;
+    riff_len + 8 == payload.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use byteorder::{ByteOrder, LittleEndian};
+    use time::Timespec;
+
+    use super::{write_frame, FwebpError, FwebpReader, PREAMBLE_EVENT};
+
+    // Seconds, event id and payload length of a frame, or the error.
+    type Outcome = Result<(i64, Option<u64>, usize), String>;
+
+    fn webp_payload(len: usize) -> Vec<u8> {
+        let mut payload = vec![0x5a; len];
+        payload[0..4].copy_from_slice(b"RIFF");
+        LittleEndian::write_u32(&mut payload[4..8], len as u32 - 8);
+        payload[8..12].copy_from_slice(b"WEBP");
+        payload
+    }
+
+    fn frame_bytes(sec: i64, event_id: Option<u64>, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_frame(&mut out, Timespec::new(sec, 500), event_id, payload).unwrap();
+        out
+    }
+
+    // Every frame read, then the bytes skipped and resyncs.
+    fn read_all(data: Vec<u8>) -> (Vec<Outcome>, u64, u64) {
+        let mut reader = FwebpReader::new(Cursor::new(data));
+        let frames = reader.by_ref()
+            .map(|r| match r {
+                Ok(f) => Ok((f.when.sec, f.event_id, f.payload.len())),
+                Err(FwebpError::Truncated) => Err("truncated".to_string()),
+                Err(err) => Err(err.to_string()),
+            })
+            .collect();
+        (frames, reader.skipped_bytes(), reader.resync_count())
+    }
+
+    #[test]
+    fn round_trips_both_preambles() {
+        let plain = webp_payload(32);
+        let tagged = webp_payload(48);
+        let mut data = frame_bytes(1, None, &plain);
+        data.extend(frame_bytes(2, Some(7), &tagged));
+
+        let mut reader = FwebpReader::new(Cursor::new(data));
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(first.when, Timespec::new(1, 500));
+        assert_eq!(first.event_id, None);
+        assert_eq!(first.payload, plain);
+        let second = reader.next().unwrap().unwrap();
+        assert_eq!(second.when, Timespec::new(2, 500));
+        assert_eq!(second.event_id, Some(7));
+        assert_eq!(second.payload, tagged);
+        assert!(reader.next().is_none());
+        assert_eq!(reader.skipped_bytes(), 0);
+        assert_eq!(reader.resync_count(), 0);
+    }
+
+    #[test]
+    fn skips_garbage_between_frames() {
+        let mut data = frame_bytes(1, None, &webp_payload(32));
+        data.extend(vec![0xa5; 37]);
+        data.extend(frame_bytes(2, Some(3), &webp_payload(32)));
+
+        let (frames, skipped, resyncs) = read_all(data);
+        assert_eq!(frames, vec![Ok((1, None, 32)), Ok((2, Some(3), 32))]);
+        assert_eq!(skipped, 37);
+        assert_eq!(resyncs, 1);
+    }
+
+    #[test]
+    fn resyncs_after_truncated_frame() {
+        let mut data = frame_bytes(1, None, &webp_payload(32));
+        let mut cut = frame_bytes(2, Some(1), &webp_payload(64));
+        let cut_len = cut.len() - 40;
+        cut.truncate(cut_len);
+        data.extend(cut);
+        // longer than what was cut off, so the claimed payload length is
+        // satisfied by the next frame's bytes.
+        data.extend(frame_bytes(3, Some(1), &webp_payload(100)));
+
+        let (frames, skipped, resyncs) = read_all(data);
+        assert_eq!(frames, vec![Ok((1, None, 32)), Ok((3, Some(1), 100))]);
+        assert_eq!(skipped, cut_len as u64);
+        assert_eq!(resyncs, 1);
+    }
+
+    #[test]
+    fn resyncs_when_cut_frame_runs_into_next_preamble() {
+        let mut cut = frame_bytes(1, Some(1), &webp_payload(64));
+        let cut_len = cut.len() - 40;
+        cut.truncate(cut_len);
+        let mut data = cut;
+        data.extend(vec![0xa5; 37]);
+        data.extend(frame_bytes(2, None, &webp_payload(32)));
+
+        let (frames, skipped, resyncs) = read_all(data);
+        assert_eq!(frames, vec![Ok((2, None, 32))]);
+        assert_eq!(skipped, cut_len as u64 + 37);
+        assert_eq!(resyncs, 1);
+    }
+
+    #[test]
+    fn resyncs_when_cut_inside_header() {
+        let mut data = frame_bytes(1, Some(1), &webp_payload(64));
+        data.truncate(PREAMBLE_EVENT.len());
+        data.extend(frame_bytes(2, None, &webp_payload(32)));
+
+        let (frames, skipped, resyncs) = read_all(data);
+        assert_eq!(frames, vec![Ok((2, None, 32))]);
+        assert_eq!(skipped, PREAMBLE_EVENT.len() as u64);
+        assert_eq!(resyncs, 1);
+    }
+
+    #[test]
+    fn truncated_final_frame_is_an_error() {
+        let mut data = frame_bytes(1, None, &webp_payload(32));
+        let mut cut = frame_bytes(2, None, &webp_payload(64));
+        let cut_len = cut.len() - 10;
+        cut.truncate(cut_len);
+        data.extend(cut);
+
+        let (frames, _, _) = read_all(data);
+        assert_eq!(frames, vec![Ok((1, None, 32)), Err("truncated".to_string())]);
+    }
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::io::{self, Write};
+
+use surface::{Surface, Yuv420p};
+
+use webp::{self, EncodeError, EncoderConfig};
+
+/// Counts, per pixel, how many frames had motion there.
+pub struct Heatmap {
+    width: u32,
+    height: u32,
+    counts: Vec<u32>,
+    frames: u64,
+}
+
+impl Heatmap {
+    pub fn new(width: u32, height: u32) -> Heatmap {
+        Heatmap {
+            width: width,
+            height: height,
+            counts: vec![0; width as usize * height as usize],
+            frames: 0,
+        }
+    }
+
+    /// Adds one frame's lit-pixel map.
+    pub fn add(&mut self, lit: &[bool]) {
+        for (count, lit) in self.counts.iter_mut().zip(lit.iter()) {
+            if *lit {
+                *count = count.saturating_add(1);
+            }
+        }
+        self.frames += 1;
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The counts scaled so the busiest pixel is white. A square root curve
+    /// keeps rarely-lit pixels visible next to constantly-lit ones.
+    pub fn normalized(&self) -> Vec<u8> {
+        let max = self.counts.iter().cloned().max().unwrap_or(0);
+        if max == 0 {
+            return vec![0; self.counts.len()];
+        }
+        let scale = 255.0 / (max as f32).sqrt();
+        self.counts.iter().map(|c| ((*c as f32).sqrt() * scale).round() as u8).collect()
+    }
+
+    /// Writes the normalized heatmap as a binary greymap.
+    pub fn write_pgm<W: Write>(&self, wri: &mut W) -> io::Result<()> {
+        try!(write!(wri, "P5\n{} {}\n255\n", self.width, self.height));
+        wri.write_all(&self.normalized()[..])
+    }
+
+    /// The normalized heatmap as a greyscale WebP.
+    pub fn encode_webp(&self, encoder: &EncoderConfig) -> Result<Vec<u8>, EncodeError> {
+        let luma = self.normalized();
+        let chroma_len = luma.len() / 2;
+        let mut yuv = luma;
+        yuv.extend(::std::iter::repeat(0x80).take(chroma_len));
+        let surface = Surface::<Yuv420p, u8, _>::new(self.width, self.height, yuv.into_boxed_slice());
+        webp::reencode(&surface, encoder)
+    }
+}
+
+/// Replaces `path` with `contents` without readers ever seeing a partial
+/// file.
+pub fn replace_file(path: &str, contents: &[u8]) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    {
+        let mut file = try!(fs::File::create(&tmp_path));
+        try!(file.write_all(contents));
+    }
+    fs::rename(&tmp_path, path)
+}
@@ -0,0 +1,285 @@
+use std::ops::Deref;
+use std::str::FromStr;
+
+use surface::{Surface, Luma};
+use surface::kernels::{Luma8Sobel3x3, Luma8Average3x3};
+
+/// An odd-sized square convolution kernel, weights in row-major order. Not
+/// necessarily separable.
+#[derive(Clone, Debug)]
+pub struct Kernel {
+    size: usize,
+    weights: Vec<f32>,
+}
+
+impl Kernel {
+    /// A `size`x`size` kernel from `weights` in row-major order. `size` has
+    /// to be odd so the kernel centres on a pixel.
+    pub fn new(size: usize, weights: Vec<f32>) -> Result<Kernel, String> {
+        if size % 2 == 0 {
+            return Err(format!("kernel size {} is not odd", size));
+        }
+        if weights.len() != size * size {
+            return Err(format!("a {}x{} kernel needs {} weights, not {}",
+                size, size, size * size, weights.len()));
+        }
+        if !weights.iter().all(|w| w.is_finite()) {
+            return Err("kernel weights must be finite".to_string());
+        }
+        Ok(Kernel { size: size, weights: weights })
+    }
+
+    pub fn scharr_x() -> Kernel {
+        Kernel::new(3, vec![-3.0, 0.0, 3.0, -10.0, 0.0, 10.0, -3.0, 0.0, 3.0]).unwrap()
+    }
+
+    pub fn scharr_y() -> Kernel {
+        Kernel::scharr_x().transposed()
+    }
+
+    pub fn laplacian() -> Kernel {
+        Kernel::new(3, vec![0.0, 1.0, 0.0, 1.0, -4.0, 1.0, 0.0, 1.0, 0.0]).unwrap()
+    }
+
+    fn transposed(&self) -> Kernel {
+        let n = self.size;
+        let mut weights = vec![0.0; n * n];
+        for y in 0..n {
+            for x in 0..n {
+                weights[x * n + y] = self.weights[y * n + x];
+            }
+        }
+        Kernel { size: n, weights: weights }
+    }
+
+    /// Convolves `src`, repeating the border pixels outwards, and returns
+    /// the unrounded results.
+    pub fn convolve<S>(&self, src: &Surface<Luma, u8, S>) -> Vec<f32>
+        where S: Deref<Target=[u8]>
+    {
+        let (width, height) = (src.width() as usize, src.height() as usize);
+        let px = src.raw_bytes();
+        let r = (self.size / 2) as isize;
+
+        let mut out = vec![0.0; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let mut acc = 0.0;
+                for ky in 0..self.size {
+                    let sy = clamp_index(y as isize + ky as isize - r, height);
+                    let row = &px[sy * width..(sy + 1) * width];
+                    let weights = &self.weights[ky * self.size..(ky + 1) * self.size];
+                    for (kx, w) in weights.iter().enumerate() {
+                        acc += *w * row[clamp_index(x as isize + kx as isize - r, width)] as f32;
+                    }
+                }
+                out[y * width + x] = acc;
+            }
+        }
+        out
+    }
+}
+
+/// A symmetric odd-length kernel applied along rows and then columns, for
+/// blurs that would be expensive as a full NxN kernel.
+#[derive(Clone, Debug)]
+pub struct SeparableKernel {
+    taps: Vec<f32>,
+}
+
+impl SeparableKernel {
+    pub fn gaussian(sigma: f32) -> SeparableKernel {
+        let radius = (3.0 * sigma).ceil() as isize;
+        let mut taps: Vec<f32> = (-radius..radius + 1)
+            .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let sum: f32 = taps.iter().sum();
+        for t in taps.iter_mut() {
+            *t /= sum;
+        }
+        SeparableKernel { taps: taps }
+    }
+
+    pub fn box_blur(radius: u32) -> SeparableKernel {
+        let len = 2 * radius as usize + 1;
+        SeparableKernel { taps: vec![1.0 / len as f32; len] }
+    }
+
+    pub fn convolve<S>(&self, src: &Surface<Luma, u8, S>) -> Surface<Luma, u8, Box<[u8]>>
+        where S: Deref<Target=[u8]>
+    {
+        let (width, height) = (src.width() as usize, src.height() as usize);
+        let px = src.raw_bytes();
+        let r = self.taps.len() / 2;
+
+        let mut rows = vec![0.0; width * height];
+        for y in 0..height {
+            let row = &px[y * width..(y + 1) * width];
+            let out = &mut rows[y * width..(y + 1) * width];
+            for x in 0..width {
+                let mut acc = 0.0;
+                if r <= x && x + r < width {
+                    // away from the ends every tap falls inside the row.
+                    for (t, p) in self.taps.iter().zip(row[x - r..].iter()) {
+                        acc += *t * *p as f32;
+                    }
+                } else {
+                    for (k, t) in self.taps.iter().enumerate() {
+                        acc += *t * row[clamp_index((x + k) as isize - r as isize, width)] as f32;
+                    }
+                }
+                out[x] = acc;
+            }
+        }
+
+        // columns a row at a time, so each tap's source row is found once.
+        let mut out = vec![0; width * height];
+        let mut acc = vec![0.0; width];
+        for y in 0..height {
+            for a in acc.iter_mut() {
+                *a = 0.0;
+            }
+            for (k, t) in self.taps.iter().enumerate() {
+                let sy = clamp_index((y + k) as isize - r as isize, height);
+                for (a, v) in acc.iter_mut().zip(rows[sy * width..(sy + 1) * width].iter()) {
+                    *a += *t * *v;
+                }
+            }
+            for (o, a) in out[y * width..(y + 1) * width].iter_mut().zip(acc.iter()) {
+                *o = to_u8(*a);
+            }
+        }
+
+        Surface::new(src.width(), src.height(), out.into_boxed_slice())
+    }
+}
+
+// Blurs are run on every frame; past a 25-tap kernel they cost more than
+// the rest of the motion stage put together.
+const MAX_SIGMA: f32 = 4.0;
+// Below this the side taps round to nothing, and far enough below it the
+// centre one divides by zero.
+const MIN_SIGMA: f32 = 0.1;
+const MAX_BOX_RADIUS: u32 = 12;
+
+/// How the motion stage smooths luma before looking for edges.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Smoothing {
+    None,
+    /// The `surface` crate's 3x3 mean.
+    Average3x3,
+    Gaussian { sigma: f32 },
+    Box { radius: u32 },
+}
+
+impl Smoothing {
+    pub fn apply<S>(&self, src: &Surface<Luma, u8, S>) -> Surface<Luma, u8, Box<[u8]>>
+        where S: Deref<Target=[u8]>
+    {
+        match *self {
+            Smoothing::None => {
+                Surface::new(src.width(), src.height(), src.raw_bytes().to_vec().into_boxed_slice())
+            }
+            Smoothing::Average3x3 => {
+                let mut out = Surface::new_black(src.width(), src.height());
+                src.run_kernel_3x3(&Luma8Average3x3, &mut out);
+                out
+            }
+            Smoothing::Gaussian { sigma } => SeparableKernel::gaussian(sigma).convolve(src),
+            Smoothing::Box { radius } => SeparableKernel::box_blur(radius).convolve(src),
+        }
+    }
+}
+
+impl FromStr for Smoothing {
+    type Err = String;
+
+    /// `none`, `average3x3`, `gaussian:SIGMA` or `box:RADIUS`, with sigma
+    /// from 0.1 to 4 and radius up to 12.
+    fn from_str(s: &str) -> Result<Smoothing, String> {
+        let mut fields = s.splitn(2, ':');
+        let name = fields.next().unwrap_or("");
+        let param = fields.next();
+        let invalid = || format!(
+            "invalid smoothing {:?}, expected none, average3x3, gaussian:SIGMA (0.1 to 4) or box:RADIUS (0 to 12)", s);
+
+        match (name, param) {
+            ("none", None) => Ok(Smoothing::None),
+            ("average3x3", None) => Ok(Smoothing::Average3x3),
+            ("gaussian", Some(sigma)) => match sigma.parse() {
+                Ok(sigma) if MIN_SIGMA <= sigma && sigma <= MAX_SIGMA => Ok(Smoothing::Gaussian { sigma: sigma }),
+                _ => Err(invalid()),
+            },
+            ("box", Some(radius)) => match radius.parse() {
+                Ok(radius) if radius <= MAX_BOX_RADIUS => Ok(Smoothing::Box { radius: radius }),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// How the motion stage turns smoothed luma into edge strength.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EdgeOperator {
+    /// The `surface` crate's 3x3 Sobel gradient magnitude.
+    Sobel,
+    /// Scharr gradient magnitude, scaled to Sobel's range so edge
+    /// thresholds carry over.
+    Scharr,
+    /// Absolute Laplacian; responds to texture rather than direction.
+    Laplacian,
+}
+
+impl EdgeOperator {
+    pub fn apply<S>(&self, src: &Surface<Luma, u8, S>) -> Surface<Luma, u8, Box<[u8]>>
+        where S: Deref<Target=[u8]>
+    {
+        let magnitude = match *self {
+            EdgeOperator::Sobel => {
+                let mut out = Surface::new_black(src.width(), src.height());
+                src.run_kernel_3x3(&Luma8Sobel3x3, &mut out);
+                return out;
+            }
+            EdgeOperator::Scharr => {
+                // Scharr's weights sum to 16 a side against Sobel's 4.
+                let gx = Kernel::scharr_x().convolve(src);
+                let gy = Kernel::scharr_y().convolve(src);
+                gx.iter().zip(gy.iter())
+                    .map(|(x, y)| to_u8((x * x + y * y).sqrt() / 4.0))
+                    .collect::<Vec<_>>()
+            }
+            EdgeOperator::Laplacian => {
+                Kernel::laplacian().convolve(src).iter().map(|v| to_u8(v.abs())).collect()
+            }
+        };
+        Surface::new(src.width(), src.height(), magnitude.into_boxed_slice())
+    }
+}
+
+impl FromStr for EdgeOperator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EdgeOperator, String> {
+        match s {
+            "sobel" => Ok(EdgeOperator::Sobel),
+            "scharr" => Ok(EdgeOperator::Scharr),
+            "laplacian" => Ok(EdgeOperator::Laplacian),
+            other => Err(format!("unknown edge operator {:?}, expected sobel, scharr or laplacian", other)),
+        }
+    }
+}
+
+fn clamp_index(i: isize, len: usize) -> usize {
+    if i < 0 {
+        0
+    } else if len as isize <= i {
+        len - 1
+    } else {
+        i as usize
+    }
+}
+
+fn to_u8(v: f32) -> u8 {
+    v.round().max(0.0).min(255.0) as u8
+}
@@ -0,0 +1,4 @@
+extern crate time;
+extern crate byteorder;
+
+pub mod fwebp;
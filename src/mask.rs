@@ -0,0 +1,305 @@
+use std::fs;
+use std::io::{self, Read};
+
+use surface::{Surface, Luma};
+
+/// Never exceeded by a `u8`, so pixels with this threshold are never lit.
+const IGNORED: u16 = 0x100;
+/// Pixels that follow the camera's threshold, whatever it currently is.
+const DEFAULT: u16 = 0x101;
+
+#[derive(Clone, Debug)]
+pub enum ZoneShape {
+    /// Vertices in capture coordinates; filled with the even-odd rule.
+    Polygon(Vec<(f32, f32)>),
+    /// Non-zero pixels are inside the zone. Must match the capture size.
+    Bitmap { width: u32, height: u32, data: Vec<u8> },
+}
+
+#[derive(Clone, Debug)]
+pub struct Zone {
+    pub name: String,
+    pub shape: ZoneShape,
+    /// Exclusion zones are never counted, whatever came before them.
+    pub exclude: bool,
+    /// Overrides the camera's edge threshold inside this zone.
+    pub threshold: Option<u8>,
+}
+
+/// Per-pixel lit thresholds compiled from a list of zones. Pixels outside
+/// any zone with its own threshold use the one passed to `apply`.
+///
+/// Without any inclusion zones the whole frame counts; otherwise only
+/// pixels inside one do. Zones are applied in order, so a later zone's
+/// threshold wins where zones overlap.
+pub struct MotionMask {
+    thresholds: Vec<u16>,
+}
+
+impl MotionMask {
+    pub fn new(width: u32, height: u32, zones: &[Zone]) -> MotionMask {
+        let (width, height) = (width as usize, height as usize);
+        let any_include = zones.iter().any(|z| !z.exclude);
+        let base = if any_include { IGNORED } else { DEFAULT };
+
+        let mut thresholds = vec![base; width * height];
+        for zone in zones.iter() {
+            let value = if zone.exclude {
+                IGNORED
+            } else {
+                zone.threshold.map_or(DEFAULT, |t| t as u16)
+            };
+
+            match zone.shape {
+                ZoneShape::Polygon(ref points) => {
+                    fill_polygon(width, height, points, |idx| thresholds[idx] = value);
+                }
+                ZoneShape::Bitmap { ref data, .. } => {
+                    for (t, px) in thresholds.iter_mut().zip(data.iter()) {
+                        if *px != 0 {
+                            *t = value;
+                        }
+                    }
+                }
+            }
+        }
+
+        MotionMask { thresholds: thresholds }
+    }
+
+    /// Zeroes every ignored pixel and marks the remaining ones that exceed
+    /// their zone's threshold, or `default_threshold`, as lit.
+    pub fn apply(&self, edge: &mut Surface<Luma, u8, Box<[u8]>>, default_threshold: u8) -> Vec<bool> {
+        let mut lit = Vec::with_capacity(self.thresholds.len());
+        for (px, threshold) in edge.raw_bytes_mut().iter_mut().zip(self.thresholds.iter()) {
+            let threshold = match *threshold {
+                IGNORED => {
+                    *px = 0;
+                    IGNORED
+                }
+                DEFAULT => default_threshold as u16,
+                threshold => threshold,
+            };
+            lit.push(threshold < *px as u16);
+        }
+        lit
+    }
+
+    /// Counts the values of the pixels that use the default threshold.
+    pub fn histogram(&self, edge: &Surface<Luma, u8, Box<[u8]>>) -> [u32; 256] {
+        let mut histogram = [0; 256];
+        for (px, threshold) in edge.raw_bytes().iter().zip(self.thresholds.iter()) {
+            if *threshold == DEFAULT {
+                histogram[*px as usize] += 1;
+            }
+        }
+        histogram
+    }
+}
+
+// Calls `set` with the index of every pixel whose centre lies inside the
+// polygon.
+fn fill_polygon<F>(width: usize, height: usize, points: &[(f32, f32)], mut set: F)
+    where F: FnMut(usize)
+{
+    if points.len() < 3 {
+        return;
+    }
+
+    let mut crossings: Vec<f32> = Vec::new();
+    for y in 0..height {
+        let yc = y as f32 + 0.5;
+
+        crossings.clear();
+        for i in 0..points.len() {
+            let (x0, y0) = points[i];
+            let (x1, y1) = points[(i + 1) % points.len()];
+            if (y0 <= yc && yc < y1) || (y1 <= yc && yc < y0) {
+                crossings.push(x0 + (yc - y0) * (x1 - x0) / (y1 - y0));
+            }
+        }
+        crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        for span in crossings.chunks(2) {
+            if span.len() < 2 {
+                break;
+            }
+            // first and last pixel centres inside [span[0], span[1]).
+            let start = (span[0] - 0.5).ceil().max(0.0) as usize;
+            let end = (span[1] - 0.5).ceil().max(0.0).min(width as f32) as usize;
+            for x in start..end {
+                set(y * width + x);
+            }
+        }
+    }
+}
+
+/// Reads a binary (P5) or plain (P2) greymap with a maxval of at most 255.
+pub fn load_pgm(path: &str) -> io::Result<(u32, u32, Vec<u8>)> {
+    let mut raw = Vec::new();
+    try!(try!(fs::File::open(path)).read_to_end(&mut raw));
+
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, msg));
+
+    let mut pos = 0;
+    let magic = try!(next_token(&raw, &mut pos).ok_or_else(|| invalid("empty file")));
+    let binary = match magic {
+        b"P5" => true,
+        b"P2" => false,
+        _ => return Err(invalid("not a PGM (expected P5 or P2)")),
+    };
+
+    let mut header = [0u32; 3];
+    for field in header.iter_mut() {
+        *field = try!(next_token(&raw, &mut pos)
+            .and_then(|tok| ::std::str::from_utf8(tok).ok())
+            .and_then(|tok| tok.parse().ok())
+            .ok_or_else(|| invalid("malformed header")));
+    }
+    let (width, height, maxval) = (header[0], header[1], header[2]);
+    if maxval == 0 || 255 < maxval {
+        return Err(invalid("only 8-bit greymaps are supported"));
+    }
+
+    let len = width as usize * height as usize;
+    let data = if binary {
+        // exactly one whitespace byte separates the header from the raster.
+        pos += 1;
+        if raw.len() < pos + len {
+            return Err(invalid("raster is truncated"));
+        }
+        raw[pos..pos + len].to_vec()
+    } else {
+        // every value takes at least two bytes, so a corrupt header can't
+        // ask for more than the file could hold.
+        let mut data = Vec::with_capacity(::std::cmp::min(len, raw.len() - pos));
+        while data.len() < len {
+            let value: u32 = try!(next_token(&raw, &mut pos)
+                .and_then(|tok| ::std::str::from_utf8(tok).ok())
+                .and_then(|tok| tok.parse().ok())
+                .ok_or_else(|| invalid("raster is truncated or malformed")));
+            data.push(::std::cmp::min(value, 255) as u8);
+        }
+        data
+    };
+
+    Ok((width, height, data))
+}
+
+// Returns the next whitespace-delimited token, skipping `#` comments.
+fn next_token<'a>(raw: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    loop {
+        while *pos < raw.len() && (raw[*pos] as char).is_whitespace() {
+            *pos += 1;
+        }
+        if *pos < raw.len() && raw[*pos] == b'#' {
+            while *pos < raw.len() && raw[*pos] != b'\n' {
+                *pos += 1;
+            }
+            continue;
+        }
+        break;
+    }
+
+    let start = *pos;
+    while *pos < raw.len() && !(raw[*pos] as char).is_whitespace() {
+        *pos += 1;
+    }
+    if start == *pos {
+        None
+    } else {
+        Some(&raw[start..*pos])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::process;
+
+    use super::{fill_polygon, load_pgm};
+
+    fn filled(width: usize, height: usize, points: &[(f32, f32)]) -> Vec<bool> {
+        let mut inside = vec![false; width * height];
+        fill_polygon(width, height, points, |idx| inside[idx] = true);
+        inside
+    }
+
+    fn rows(width: usize, inside: &[bool]) -> Vec<String> {
+        inside.chunks(width)
+            .map(|row| row.iter().map(|&i| if i { '#' } else { '.' }).collect())
+            .collect()
+    }
+
+    fn with_pgm<F: FnOnce(&str)>(name: &str, contents: &[u8], f: F) {
+        let path = env::temp_dir().join(format!("camcap-mask-{}-{}.pgm", process::id(), name));
+        fs::File::create(&path).unwrap().write_all(contents).unwrap();
+        f(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fills_pixels_whose_centres_are_inside() {
+        let inside = filled(6, 4, &[(1.0, 1.0), (4.0, 1.0), (4.0, 3.0), (1.0, 3.0)]);
+        assert_eq!(rows(6, &inside), vec!["......", ".###..", ".###..", "......"]);
+    }
+
+    #[test]
+    fn centres_on_the_left_and_top_edges_are_inside() {
+        let inside = filled(4, 4, &[(0.5, 0.5), (2.5, 0.5), (2.5, 2.5), (0.5, 2.5)]);
+        assert_eq!(rows(4, &inside), vec!["##..", "##..", "....", "...."]);
+    }
+
+    #[test]
+    fn leaves_the_notch_of_a_concave_polygon_empty() {
+        let u = [(0.0, 0.0), (1.0, 0.0), (1.0, 3.0), (4.0, 3.0), (4.0, 0.0), (5.0, 0.0),
+                 (5.0, 4.0), (0.0, 4.0)];
+        let inside = filled(5, 4, &u);
+        assert_eq!(rows(5, &inside), vec!["#...#", "#...#", "#...#", "#####"]);
+    }
+
+    #[test]
+    fn clips_polygons_to_the_frame() {
+        let inside = filled(4, 3, &[(-5.0, -5.0), (2.0, -5.0), (2.0, 100.0), (-5.0, 100.0)]);
+        assert_eq!(rows(4, &inside), vec!["##..", "##..", "##.."]);
+    }
+
+    #[test]
+    fn reads_binary_pgm() {
+        let mut contents = b"P5\n# a comment\n3 2\n255\n".to_vec();
+        contents.extend_from_slice(&[0, 1, 2, 253, 254, 255]);
+        with_pgm("binary", &contents, |path| {
+            assert_eq!(load_pgm(path).unwrap(), (3, 2, vec![0, 1, 2, 253, 254, 255]));
+        });
+    }
+
+    #[test]
+    fn reads_plain_pgm() {
+        with_pgm("plain", b"P2\n3 2 255\n0 1 2\n# comment\n253 254 300\n", |path| {
+            assert_eq!(load_pgm(path).unwrap(), (3, 2, vec![0, 1, 2, 253, 254, 255]));
+        });
+    }
+
+    #[test]
+    fn rejects_truncated_rasters() {
+        with_pgm("short-binary", b"P5 4 4 255\n\x01\x02", |path| {
+            assert!(load_pgm(path).is_err());
+        });
+        // far too large to allocate if taken at its word.
+        with_pgm("short-plain", b"P2 4000000000 4000000000 255\n1 2 3\n", |path| {
+            assert!(load_pgm(path).is_err());
+        });
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        with_pgm("ppm", b"P6 1 1 255\n\x00\x00\x00", |path| {
+            assert!(load_pgm(path).is_err());
+        });
+        with_pgm("deep", b"P5 1 1 65535\n\x00\x00", |path| {
+            assert!(load_pgm(path).is_err());
+        });
+    }
+}
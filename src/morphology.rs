@@ -0,0 +1,170 @@
+use std::cmp;
+use std::str::FromStr;
+
+use surface::{Surface, Luma};
+
+// Each offset in an element is a pass over the whole frame, on every frame,
+// so elements stay small: a radius of 4 is already 81 passes as a square.
+const MAX_RADIUS: u32 = 4;
+
+/// The neighbourhood, as offsets from the centre pixel, that each pixel is
+/// eroded or dilated over.
+#[derive(Clone, Debug)]
+pub struct StructuringElement {
+    offsets: Vec<(isize, isize)>,
+}
+
+impl StructuringElement {
+    /// A (2r+1)x(2r+1) square; radius 1 is the usual 3x3.
+    pub fn square(radius: u32) -> StructuringElement {
+        StructuringElement::from_fn(radius, |_, _| true)
+    }
+
+    /// A plus sign with arms `radius` long.
+    pub fn cross(radius: u32) -> StructuringElement {
+        StructuringElement::from_fn(radius, |dx, dy| dx == 0 || dy == 0)
+    }
+
+    pub fn disk(radius: u32) -> StructuringElement {
+        let r2 = radius as isize * radius as isize;
+        StructuringElement::from_fn(radius, |dx, dy| dx * dx + dy * dy <= r2)
+    }
+
+    fn from_fn<F>(radius: u32, inside: F) -> StructuringElement
+        where F: Fn(isize, isize) -> bool
+    {
+        let r = radius as isize;
+        let mut offsets = Vec::new();
+        for dy in -r..r + 1 {
+            for dx in -r..r + 1 {
+                if inside(dx, dy) {
+                    offsets.push((dx, dy));
+                }
+            }
+        }
+        StructuringElement { offsets: offsets }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MorphOp {
+    Erode,
+    Dilate,
+    /// Erode then dilate: removes specks smaller than the element.
+    Open,
+    /// Dilate then erode: fills gaps smaller than the element.
+    Close,
+}
+
+/// One operation of a morphology pass, written `OP[:SHAPE[:RADIUS]]`, e.g.
+/// `open` or `close:disk:2`. The element defaults to a 3x3 square, and its
+/// radius can be at most 4.
+#[derive(Clone, Debug)]
+pub struct MorphStep {
+    pub op: MorphOp,
+    pub element: StructuringElement,
+}
+
+impl FromStr for MorphStep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MorphStep, String> {
+        let mut fields = s.trim().split(':');
+        let op = match fields.next() {
+            Some("erode") => MorphOp::Erode,
+            Some("dilate") => MorphOp::Dilate,
+            Some("open") => MorphOp::Open,
+            Some("close") => MorphOp::Close,
+            _ => return Err(format!(
+                "unknown morphology operation {:?}, expected erode, dilate, open or close", s)),
+        };
+        let shape = fields.next().unwrap_or("square");
+        let radius = match fields.next() {
+            Some(radius) => match radius.parse() {
+                Ok(radius) if radius <= MAX_RADIUS => radius,
+                _ => return Err(format!(
+                    "invalid structuring element radius {:?} in {:?}, expected 0 to {}", radius, s, MAX_RADIUS)),
+            },
+            None => 1,
+        };
+        if fields.next().is_some() {
+            return Err(format!("invalid morphology step {:?}, expected OP[:SHAPE[:RADIUS]]", s));
+        }
+        let element = match shape {
+            "square" => StructuringElement::square(radius),
+            "cross" => StructuringElement::cross(radius),
+            "disk" => StructuringElement::disk(radius),
+            other => return Err(format!(
+                "unknown structuring element {:?}, expected square, cross or disk", other)),
+        };
+
+        Ok(MorphStep { op: op, element: element })
+    }
+}
+
+impl MorphStep {
+    pub fn apply(&self, surf: &Surface<Luma, u8, Box<[u8]>>) -> Surface<Luma, u8, Box<[u8]>> {
+        match self.op {
+            MorphOp::Erode => erode(surf, &self.element),
+            MorphOp::Dilate => dilate(surf, &self.element),
+            MorphOp::Open => open(surf, &self.element),
+            MorphOp::Close => close(surf, &self.element),
+        }
+    }
+}
+
+/// Each pixel becomes the darkest in its neighbourhood.
+pub fn erode(surf: &Surface<Luma, u8, Box<[u8]>>, element: &StructuringElement)
+    -> Surface<Luma, u8, Box<[u8]>>
+{
+    morph(surf, element, 0xFF, cmp::min)
+}
+
+/// Each pixel becomes the brightest in its neighbourhood.
+pub fn dilate(surf: &Surface<Luma, u8, Box<[u8]>>, element: &StructuringElement)
+    -> Surface<Luma, u8, Box<[u8]>>
+{
+    morph(surf, element, 0x00, cmp::max)
+}
+
+pub fn open(surf: &Surface<Luma, u8, Box<[u8]>>, element: &StructuringElement)
+    -> Surface<Luma, u8, Box<[u8]>>
+{
+    dilate(&erode(surf, element), element)
+}
+
+pub fn close(surf: &Surface<Luma, u8, Box<[u8]>>, element: &StructuringElement)
+    -> Surface<Luma, u8, Box<[u8]>>
+{
+    erode(&dilate(surf, element), element)
+}
+
+// Folds `combine` over every pixel's neighbourhood. Neighbours outside the
+// frame are left out rather than padded.
+fn morph<F>(surf: &Surface<Luma, u8, Box<[u8]>>, element: &StructuringElement, identity: u8, combine: F)
+    -> Surface<Luma, u8, Box<[u8]>>
+    where F: Fn(u8, u8) -> u8
+{
+    let (width, height) = (surf.width() as isize, surf.height() as isize);
+    let src = surf.raw_bytes();
+    let mut out = vec![identity; src.len()];
+
+    // one pass per offset, so the inner loop is a straight run over a row.
+    for &(dx, dy) in element.offsets.iter() {
+        let (x0, x1) = (cmp::max(0, -dx), cmp::min(width, width - dx));
+        let (y0, y1) = (cmp::max(0, -dy), cmp::min(height, height - dy));
+        if x1 <= x0 {
+            continue;
+        }
+        for y in y0..y1 {
+            let out_row = &mut out[(y * width + x0) as usize..(y * width + x1) as usize];
+            let src_start = ((y + dy) * width + x0 + dx) as usize;
+            let src_row = &src[src_start..src_start + out_row.len()];
+            for (o, s) in out_row.iter_mut().zip(src_row.iter()) {
+                *o = combine(*o, *s);
+            }
+        }
+    }
+
+    Surface::new(surf.width(), surf.height(), out.into_boxed_slice())
+}
@@ -0,0 +1,393 @@
+use std::collections::VecDeque;
+
+use time::{Duration, Timespec};
+use surface::{Surface, Luma, Yuv420p};
+
+use blob::{self, Blob};
+use detector::{DetectorKind, DetectorInput, MotionDetector};
+use kernel::{EdgeOperator, Smoothing};
+use mask::{MotionMask, Zone};
+use morphology::MorphStep;
+use tamper::{TamperConfig, TamperDetector, TamperEvent, TamperKind};
+use threshold::{AdaptiveThreshold, NoiseFloor};
+use tracker::{Track, Tracker};
+use tripwire::{Crossing, Direction, Tripwire};
+
+#[derive(Clone, Debug)]
+pub struct MotionConfig {
+    /// Applied to luma before edges are found, to keep sensor noise out of
+    /// them.
+    pub smoothing: Smoothing,
+    pub edge_operator: EdgeOperator,
+    /// Edge-difference values above this count as a lit pixel, except in
+    /// zones with their own threshold.
+    pub edge_threshold: u8,
+    /// Applied in order to the detector output before it's thresholded.
+    pub morphology: Vec<MorphStep>,
+    /// Replaces `edge_threshold` with one that follows the noise floor.
+    pub adaptive_threshold: Option<AdaptiveThreshold>,
+    /// Trigger when the lit pixels averaged over the pre-roll window exceed
+    /// this fraction of the frame. Only blobs on tracks that have lasted
+    /// `min_track_frames` count.
+    pub min_lit_fraction: f64,
+    /// How far before a trigger a recording starts. Frames are held back
+    /// this long before they're emitted or dropped.
+    pub pre_roll: Duration,
+    /// How long recording continues after the most recent trigger.
+    pub post_roll: Duration,
+    /// Regions to watch or ignore; empty means the whole frame counts.
+    pub zones: Vec<Zone>,
+    pub detector: DetectorKind,
+    /// Weight of each new frame in the background-model detectors.
+    pub learning_rate: f32,
+    /// Connected groups of lit pixels smaller than this are ignored as noise.
+    pub min_blob_area: usize,
+    /// Frames a blob has to be followed for before it counts as motion;
+    /// 1 counts every blob straight away.
+    pub min_track_frames: u32,
+    /// How far, in pixels, a blob may be from where a track was heading and
+    /// still continue it.
+    pub track_max_distance: f32,
+    /// Frames a track survives without a matching blob.
+    pub track_max_missed: u32,
+    /// Lines to count tracks crossing. When there are any, only crossings
+    /// trigger a recording.
+    pub tripwires: Vec<Tripwire>,
+    /// A change in mean luma, in levels, this large from one frame to the
+    /// next is taken for lighting rather than motion: the frame can't
+    /// trigger and the detector re-learns its background from it. `None`
+    /// disables the check.
+    pub illumination_threshold: Option<f32>,
+    /// Watch for the camera being covered, defocused or moved. While it's
+    /// covered, up to the tamper `relearn_after`, motion can't trigger a
+    /// recording.
+    pub tamper: Option<TamperConfig>,
+}
+
+impl Default for MotionConfig {
+    fn default() -> MotionConfig {
+        MotionConfig {
+            smoothing: Smoothing::Average3x3,
+            edge_operator: EdgeOperator::Sobel,
+            edge_threshold: 0x60,
+            morphology: Vec::new(),
+            adaptive_threshold: None,
+            // 100 lit pixels per frame at 1280x960.
+            min_lit_fraction: 100.0 / (1280.0 * 960.0),
+            pre_roll: Duration::seconds(2),
+            post_roll: Duration::seconds(2),
+            zones: Vec::new(),
+            detector: DetectorKind::EdgeDiff,
+            learning_rate: 0.05,
+            min_blob_area: 1,
+            min_track_frames: 1,
+            track_max_distance: 64.0,
+            track_max_missed: 2,
+            tripwires: Vec::new(),
+            illumination_threshold: Some(20.0),
+            tamper: None,
+        }
+    }
+}
+
+/// A span of recorded frames around one or more overlapping triggers.
+#[derive(Clone, Debug)]
+pub struct MotionEvent {
+    pub id: u64,
+    /// When the first trigger fired.
+    pub triggered_at: Timespec,
+    /// Capture time of the first recorded frame, pre-roll included.
+    pub start: Timespec,
+    /// Capture time of the last recorded frame.
+    pub end: Timespec,
+    /// Most lit pixels, in blobs large enough to count, seen in any one
+    /// recorded frame.
+    pub peak_lit_pixels: usize,
+    pub frame_count: usize,
+    /// Recorded frames whose motion was ignored as a lighting change.
+    pub illumination_changes: usize,
+}
+
+pub enum MotionOutput {
+    /// A frame to record, tagged with the event it belongs to.
+    Frame {
+        event_id: u64,
+        when: Timespec,
+        surface: Surface<Yuv420p, u8, Box<[u8]>>,
+        /// The motion found in this frame, largest first.
+        blobs: Vec<Blob>,
+        /// Tracks that had a blob in this frame and have lasted long enough
+        /// to count. Blobs are only tracked when `min_track_frames` is above
+        /// 1 or there are tripwires; otherwise this is empty.
+        tracks: Vec<Track>,
+    },
+    /// The event has recorded its last frame.
+    EventEnd(MotionEvent),
+    /// A track crossed a tripwire in the frame just pushed.
+    Crossing(Crossing),
+    /// The camera has been covered or moved, as of the frame just pushed.
+    /// Its times are on the motion clock, which only differs from capture
+    /// time after the wall clock has been stepped back.
+    Tamper(TamperEvent),
+}
+
+struct RecentFrame {
+    when: Timespec,
+    /// `when` on the motion clock.
+    at: Timespec,
+    lit_pixels: usize,
+    illumination_change: bool,
+    blobs: Vec<Blob>,
+    tracks: Vec<Track>,
+    surface: Surface<Yuv420p, u8, Box<[u8]>>,
+}
+
+pub struct MotionContext {
+    detector: Box<MotionDetector>,
+    pub last_edge: Surface<Luma, u8, Box<[u8]>>,
+    /// Which pixels of `last_edge` counted as lit, row-major.
+    pub last_lit: Vec<bool>,
+    recents: VecDeque<RecentFrame>,
+    mask: MotionMask,
+    noise_floor: Option<NoiseFloor>,
+    tracker: Tracker,
+    tamper: Option<TamperDetector>,
+    /// Forward and backward crossings so far, by tripwire.
+    crossing_counts: Vec<(u64, u64)>,
+    last_luma_mean: Option<f32>,
+    /// The last frame's capture time and the time it was given on the
+    /// motion clock, which follows capture times but never goes backwards,
+    /// so a wall clock stepped back can't hold frames in the pre-roll
+    /// window or stretch a recording until it catches up.
+    clock: Option<(Timespec, Timespec)>,
+    /// On the motion clock.
+    emit_until: Option<Timespec>,
+    event: Option<MotionEvent>,
+    next_event_id: u64,
+    config: MotionConfig,
+}
+
+impl MotionContext {
+    pub fn new(width: u32, height: u32, config: &MotionConfig) -> MotionContext {
+        MotionContext {
+            detector: config.detector.build(width, height, config.learning_rate),
+            last_edge: Surface::new_black(width, height),
+            last_lit: vec![false; width as usize * height as usize],
+            recents: VecDeque::new(),
+            mask: MotionMask::new(width, height, &config.zones),
+            noise_floor: config.adaptive_threshold.map(NoiseFloor::new),
+            tracker: Tracker::new(config.track_max_distance, config.track_max_missed),
+            tamper: config.tamper.map(TamperDetector::new),
+            crossing_counts: vec![(0, 0); config.tripwires.len()],
+            last_luma_mean: None,
+            clock: None,
+            emit_until: None,
+            event: None,
+            next_event_id: 1,
+            config: config.clone(),
+        }
+    }
+
+    /// Takes the frame captured at `when` and returns the frames that have
+    /// left the pre-roll window and fall within an event, oldest first,
+    /// along with the end of any event that has finished and any tripwire
+    /// crossings or tampering seen in this frame.
+    pub fn push_pop(&mut self, when: Timespec, frame: Surface<Yuv420p, u8, Box<[u8]>>)
+        -> Vec<MotionOutput>
+    {
+        let at = match self.clock {
+            Some((last_when, last_at)) if last_when < when => last_at + (when - last_when),
+            Some((_, last_at)) => last_at,
+            None => when,
+        };
+        self.clock = Some((when, at));
+
+        let (tmp, edge) = {
+            let (y_p, _, _) = frame.get_planes();
+            let frame_luma = Surface::<Luma, u8, _>::new(frame.width(), frame.height(), y_p);
+
+            let tmp = self.config.smoothing.apply(&frame_luma);
+            let edge = self.config.edge_operator.apply(&tmp);
+
+            (tmp, edge)
+        };
+
+        let luma_mean = tmp.raw_bytes().iter().map(|px| *px as u64).sum::<u64>() as f32
+            / tmp.raw_bytes().len() as f32;
+        let illumination_change = match (self.last_luma_mean, self.config.illumination_threshold) {
+            (Some(last), Some(threshold)) => threshold <= (luma_mean - last).abs(),
+            _ => false,
+        };
+        self.last_luma_mean = Some(luma_mean);
+        let tamper_event = match self.tamper {
+            Some(ref mut tamper) => tamper.update(at, edge.raw_bytes()),
+            None => None,
+        };
+        let moved = tamper_event.as_ref().map_or(false, |t| t.kind == TamperKind::Moved);
+        let obscured = self.tamper.as_ref().map_or(false, |t| t.active() == Some(TamperKind::Obscured));
+
+        let rebaselined = illumination_change || moved;
+        if rebaselined {
+            self.detector.reset();
+        }
+
+        self.last_edge = self.detector.detect(&DetectorInput {
+            luma: &tmp,
+            edge: &edge,
+        });
+        for step in self.config.morphology.iter() {
+            self.last_edge = step.apply(&self.last_edge);
+        }
+
+        let threshold = match self.noise_floor {
+            Some(ref mut noise_floor) => {
+                // a freshly reset detector outputs nothing, which isn't noise.
+                if !rebaselined {
+                    noise_floor.update(&self.mask.histogram(&self.last_edge));
+                }
+                noise_floor.threshold()
+            }
+            None => self.config.edge_threshold,
+        };
+        self.last_lit = self.mask.apply(&mut self.last_edge, threshold);
+        let mut blobs = blob::find_blobs(frame.width(), frame.height(), &self.last_lit,
+            self.config.min_blob_area);
+        blobs.sort_by(|a, b| b.area.cmp(&a.area));
+
+        // with every blob counting and nothing to cross, tracks change nothing.
+        let (tracks, lit_pixels) = if 1 < self.config.min_track_frames || !self.config.tripwires.is_empty() {
+            let track_ids = self.tracker.update(at, &blobs);
+            let min_track_frames = self.config.min_track_frames;
+            let tracks: Vec<Track> = self.tracker.tracks().iter()
+                .filter(|t| t.misses == 0 && min_track_frames <= t.hits)
+                .cloned()
+                .collect();
+            let lit_pixels = blobs.iter().zip(track_ids.iter())
+                .filter(|&(_, id)| tracks.iter().any(|t| t.id == *id))
+                .map(|(b, _)| b.area)
+                .sum();
+            (tracks, lit_pixels)
+        } else {
+            (Vec::new(), blobs.iter().map(|b| b.area).sum())
+        };
+
+        let mut crossings = Vec::new();
+        for (tripwire, counts) in self.config.tripwires.iter().zip(self.crossing_counts.iter_mut()) {
+            for track in tracks.iter() {
+                let direction = match tripwire.crossed_by(track) {
+                    Some(direction) => direction,
+                    None => continue,
+                };
+                let count = match direction {
+                    Direction::Forward => &mut counts.0,
+                    Direction::Backward => &mut counts.1,
+                };
+                *count += 1;
+                crossings.push(Crossing {
+                    tripwire: tripwire.name.clone(),
+                    direction: direction,
+                    track_id: track.id,
+                    when: when,
+                    count: *count,
+                });
+            }
+        }
+
+        self.recents.push_back(RecentFrame {
+            when: when,
+            at: at,
+            lit_pixels: lit_pixels,
+            illumination_change: illumination_change,
+            blobs: blobs,
+            tracks: tracks,
+            surface: frame,
+        });
+
+        let triggered = if rebaselined || obscured {
+            false
+        } else if self.config.tripwires.is_empty() {
+            let pixel_count = self.last_edge.raw_bytes().len() as f64;
+            let trigger_lit = self.recents.len() as f64 * self.config.min_lit_fraction * pixel_count;
+            trigger_lit < self.recents.iter().map(|r| r.lit_pixels).sum::<usize>() as f64
+        } else {
+            !crossings.is_empty()
+        };
+        if triggered {
+            self.emit_until = Some(at + self.config.post_roll);
+            if self.event.is_none() {
+                self.event = Some(MotionEvent {
+                    id: self.next_event_id,
+                    triggered_at: when,
+                    start: when,
+                    end: when,
+                    peak_lit_pixels: 0,
+                    frame_count: 0,
+                    illumination_changes: 0,
+                });
+                self.next_event_id += 1;
+            }
+        }
+
+        let mut output = Vec::new();
+        while let Some(oldest) = self.recents.front().map(|r| r.at) {
+            if at - oldest <= self.config.pre_roll {
+                break;
+            }
+            let recent = self.recents.pop_front().unwrap();
+            self.route_frame(recent, &mut output);
+        }
+        output.extend(crossings.into_iter().map(MotionOutput::Crossing));
+        output.extend(tamper_event.into_iter().map(MotionOutput::Tamper));
+        output
+    }
+
+    /// Releases whatever is still held in the pre-roll window and closes the
+    /// open event, for when the source has run out of frames.
+    pub fn flush(&mut self) -> Vec<MotionOutput> {
+        let mut output = Vec::new();
+        while let Some(recent) = self.recents.pop_front() {
+            self.route_frame(recent, &mut output);
+        }
+        if let Some(event) = self.event.take() {
+            output.push(MotionOutput::EventEnd(event));
+        }
+        output
+    }
+
+    fn route_frame(&mut self, recent: RecentFrame, output: &mut Vec<MotionOutput>) {
+        let recording = match self.emit_until {
+            Some(until) => recent.at <= until,
+            None => false,
+        };
+
+        if !recording {
+            if let Some(event) = self.event.take() {
+                output.push(MotionOutput::EventEnd(event));
+            }
+            return;
+        }
+
+        // only reachable without an event if the capture clock went backwards.
+        let event = match self.event.as_mut() {
+            Some(event) => event,
+            None => return,
+        };
+        if event.frame_count == 0 {
+            event.start = recent.when;
+        }
+        event.end = recent.when;
+        event.peak_lit_pixels = ::std::cmp::max(event.peak_lit_pixels, recent.lit_pixels);
+        event.frame_count += 1;
+        if recent.illumination_change {
+            event.illumination_changes += 1;
+        }
+
+        output.push(MotionOutput::Frame {
+            event_id: event.id,
+            when: recent.when,
+            surface: recent.surface,
+            blobs: recent.blobs,
+            tracks: recent.tracks,
+        });
+    }
+}
@@ -0,0 +1,461 @@
+use std::fs;
+use std::io::{self, Write};
+use std::sync::mpsc::{sync_channel, RecvTimeoutError};
+use std::thread;
+
+use time::{self, Duration};
+use surface::{Surface, Luma, Yuv422p};
+
+use camcap::fwebp;
+use blob::Blob;
+use conversions::{yuyv_interleave_to_yuv422p, downsample_yuyv_420p};
+use encode_pool::{EncodePool, Encoded};
+use heatmap::{self, Heatmap};
+use motion::{MotionContext, MotionConfig, MotionOutput};
+use punchcat::PunchCat;
+use source::{FrameSource, Pacing, V4l2Source, ReplaySource, PatternSource};
+use supervisor::PipelineStatus;
+use tracker::Track;
+use webp::EncoderConfig;
+
+// How long to wait for a captured frame before writing out whatever has
+// finished encoding anyway, so a stalled source doesn't hold frames back.
+const WRITE_POLL_MS: i64 = 100;
+
+#[derive(Clone, Debug)]
+pub enum SourceKind {
+    Device(String),
+    Replay(String),
+    Pattern,
+}
+
+/// Which of the output files a pipeline writes.
+#[derive(Clone, Copy, Debug)]
+pub struct Streams {
+    /// Motion-triggered WebP frames, `.fwebp`, plus an `.events` log and,
+    /// with tripwires or tamper detection, `.crossings` and `.tamper` logs.
+    pub fullsize: bool,
+    /// Every captured frame as planar YUV, `.yuv422p`.
+    pub raw: bool,
+    /// The motion stage's edge difference, `.edge.yuv420p`.
+    pub edge: bool,
+    /// Where motion has been seen so far, `.heatmap.pgm` and
+    /// `.heatmap.webp`, rewritten every `heatmap_interval`.
+    pub heatmap: bool,
+}
+
+impl Streams {
+    pub fn parse_list<'a, I>(names: I) -> Result<Streams, String>
+        where I: IntoIterator<Item=&'a str>
+    {
+        let mut streams = Streams { fullsize: false, raw: false, edge: false, heatmap: false };
+        for name in names {
+            match name.trim() {
+                "fullsize" => streams.fullsize = true,
+                "raw" => streams.raw = true,
+                "edge" => streams.edge = true,
+                "heatmap" => streams.heatmap = true,
+                other => return Err(format!(
+                    "unknown stream {:?}, expected fullsize, raw, edge or heatmap", other)),
+            }
+        }
+        Ok(streams)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PipelineConfig {
+    pub name: String,
+    pub source: SourceKind,
+    pub pacing: Pacing,
+    pub prefix: String,
+    pub width: u32,
+    pub height: u32,
+    /// Seconds per frame as a fraction, as V4L2 expresses it.
+    pub interval: (u32, u32),
+    pub fourcc: [u8; 4],
+    pub streams: Streams,
+    pub keep_shift: u8,
+    pub punch_shift: u8,
+    pub encoder: EncoderConfig,
+    /// Worker threads encoding fullsize frames.
+    pub encode_threads: usize,
+    pub motion: MotionConfig,
+    pub heatmap_interval: Duration,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> PipelineConfig {
+        PipelineConfig {
+            name: "camera".to_string(),
+            source: SourceKind::Device("/dev/video0".to_string()),
+            pacing: Pacing::FullSpeed,
+            prefix: String::new(),
+            width: 1280,
+            height: 960,
+            interval: (1, 5),
+            fourcc: *b"YUYV",
+            streams: Streams { fullsize: true, raw: true, edge: true, heatmap: false },
+            keep_shift: 27,
+            punch_shift: 26,
+            encoder: EncoderConfig::default(),
+            encode_threads: 2,
+            motion: MotionConfig::default(),
+            heatmap_interval: Duration::minutes(5),
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub fn frame_interval(&self) -> Duration {
+        let (num, den) = self.interval;
+        Duration::nanoseconds(num as i64 * 1_000_000_000 / den as i64)
+    }
+
+    fn open_source(&self) -> io::Result<Box<FrameSource>> {
+        let (width, height) = (self.width, self.height);
+        match self.source {
+            SourceKind::Replay(ref path) => {
+                let replay_file = try!(fs::File::open(path));
+                Ok(Box::new(ReplaySource::new(io::BufReader::new(replay_file),
+                    width, height, self.frame_interval(), self.pacing)))
+            }
+            SourceKind::Pattern => {
+                Ok(Box::new(PatternSource::new(width, height, self.frame_interval(), self.pacing)))
+            }
+            SourceKind::Device(ref device) => {
+                Ok(Box::new(try!(V4l2Source::open(device, width, height, self.interval))))
+            }
+        }
+    }
+}
+
+/// Captures from the configured source until it is exhausted, writing the
+/// enabled output streams and keeping `status` up to date.
+pub fn run(config: PipelineConfig, status: &PipelineStatus) -> io::Result<()> {
+    let (width, height) = (config.width, config.height);
+    let mut source = try!(config.open_source());
+
+    let format = source.format();
+    if (format.width, format.height) != (width, height) || format.fourcc != config.fourcc {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "source delivers {}x{} {}, expected {}x{} {}",
+            format.width, format.height, String::from_utf8_lossy(&format.fourcc),
+            width, height, String::from_utf8_lossy(&config.fourcc))));
+    }
+
+    let prefix = &config.prefix;
+    let now = time::get_time();
+
+    let mut frameout_fs = None;
+    if config.streams.fullsize {
+        let filename_fs = format!("{}_{}.{:09}_fs.fwebp", prefix, now.sec, now.nsec);
+        println!("[{}] writing fullsize to {}", config.name, filename_fs);
+
+        let filename_events = format!("{}_{}.{:09}_fs.events", prefix, now.sec, now.nsec);
+        println!("[{}] writing events to {}", config.name, filename_events);
+
+        let mut crossings = None;
+        if !config.motion.tripwires.is_empty() {
+            let filename_crossings = format!("{}_{}.{:09}_fs.crossings", prefix, now.sec, now.nsec);
+            println!("[{}] writing crossings to {}", config.name, filename_crossings);
+            crossings = Some(try!(fs::File::create(&filename_crossings)));
+        }
+
+        let mut tamper = None;
+        if config.motion.tamper.is_some() {
+            let filename_tamper = format!("{}_{}.{:09}_fs.tamper", prefix, now.sec, now.nsec);
+            println!("[{}] writing tamper events to {}", config.name, filename_tamper);
+            tamper = Some(try!(fs::File::create(&filename_tamper)));
+        }
+
+        frameout_fs = Some(FullsizeOutput {
+            pool: try!(EncodePool::new(&config.name, config.encode_threads, config.encoder)),
+            frames: try!(fs::File::create(&filename_fs)),
+            events: try!(fs::File::create(&filename_events)),
+            crossings: crossings,
+            tamper: tamper,
+        });
+    }
+
+    let mut frameout_yuv = None;
+    if config.streams.raw {
+        let filename_yuv = format!("{}_{}.{:09}.yuv422p", prefix, now.sec, now.nsec);
+        println!("[{}] writing raw to {} | interval = {}", config.name, filename_yuv, 2 * width * height);
+        let file = try!(fs::File::create(&filename_yuv));
+        frameout_yuv = Some(PunchCat::new(config.keep_shift, config.punch_shift, file));
+    }
+
+    let mut frameout_edge = None;
+    if config.streams.edge {
+        let filename_edge = format!("{}_{}.{:09}.edge.yuv420p", prefix, now.sec, now.nsec);
+        println!("[{}] writing raw to {} | interval = {}", config.name, filename_edge, 3 * width * height / 2);
+        let file = try!(fs::File::create(&filename_edge));
+        frameout_edge = Some(PunchCat::new(config.keep_shift, config.punch_shift, file));
+    }
+
+    let mut heatmap_out = None;
+    if config.streams.heatmap {
+        let filename_heatmap = format!("{}_{}.{:09}.heatmap", prefix, now.sec, now.nsec);
+        println!("[{}] writing heatmap to {}.pgm and .webp", config.name, filename_heatmap);
+        heatmap_out = Some(HeatmapOutput {
+            heatmap: Heatmap::new(width, height),
+            filename: filename_heatmap,
+            next_write: None,
+        });
+    }
+
+    let mut mctx = MotionContext::new(width, height, &config.motion);
+
+    let (tx, rx) = sync_channel(10);
+    let camera_thread = thread::spawn(move || -> io::Result<()> {
+        for i in 0_u64.. {
+            match try!(source.next_frame()) {
+                Some(frame) => {
+                    if tx.send((i, frame.when, frame.surface)).is_err() {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    });
+
+    let mut out_surf = Surface::<Yuv422p, u8, _>::new_black(width, height);
+    let mut frame_count = 0;
+    let write_poll = Duration::milliseconds(WRITE_POLL_MS).to_std().unwrap();
+    loop {
+        let (i, frame_when, surf) = match rx.recv_timeout(write_poll) {
+            Ok(frame) => frame,
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(ref mut frameout_fs) = frameout_fs {
+                    let encoded = frameout_fs.pool.finished();
+                    try!(write_encoded(&config, status, frameout_fs, encoded));
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        frame_count = i + 1;
+        status.frame_captured(frame_when);
+
+        if let Some(ref mut frameout_yuv) = frameout_yuv {
+            yuyv_interleave_to_yuv422p(&surf, &mut out_surf);
+            try!(frameout_yuv.write_all(out_surf.raw_bytes()));
+        }
+
+        let surf_ds = downsample_yuyv_420p(&surf);
+        for output in mctx.push_pop(frame_when, surf_ds) {
+            try!(handle_motion_output(&config, &mut frameout_fs, i, output));
+        }
+        if let Some(ref mut frameout_fs) = frameout_fs {
+            let encoded = frameout_fs.pool.finished();
+            try!(write_encoded(&config, status, frameout_fs, encoded));
+        }
+
+        if let Some(ref mut frameout_edge) = frameout_edge {
+            try!(write_lumasurface_yuv420p(frameout_edge, &mctx.last_edge));
+        }
+
+        if let Some(ref mut heatmap_out) = heatmap_out {
+            heatmap_out.heatmap.add(&mctx.last_lit);
+            let due = match heatmap_out.next_write {
+                Some(next_write) => next_write <= frame_when,
+                None => {
+                    heatmap_out.next_write = Some(frame_when + config.heatmap_interval);
+                    false
+                }
+            };
+            if due {
+                try!(heatmap_out.write(&config));
+                heatmap_out.next_write = Some(frame_when + config.heatmap_interval);
+            }
+        }
+    }
+
+    if let Some(ref heatmap_out) = heatmap_out {
+        try!(heatmap_out.write(&config));
+    }
+
+    for output in mctx.flush() {
+        try!(handle_motion_output(&config, &mut frameout_fs, frame_count, output));
+    }
+    if let Some(ref mut frameout_fs) = frameout_fs {
+        let encoded = try!(frameout_fs.pool.finish());
+        try!(write_encoded(&config, status, frameout_fs, encoded));
+    }
+
+    match camera_thread.join() {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::Other,
+            format!("{} capture thread panicked", config.name))),
+    }
+}
+
+/// What's needed to write out and log a frame once it has been encoded.
+struct PendingFrame {
+    /// Index of the newest captured frame when this one was emitted.
+    index: u64,
+    event_id: u64,
+    when: time::Timespec,
+    blobs: Vec<Blob>,
+    tracks: Vec<Track>,
+}
+
+struct FullsizeOutput {
+    pool: EncodePool<PendingFrame>,
+    frames: fs::File,
+    /// One tab-separated line per finished event: id, trigger time, first
+    /// and last frame time, peak lit pixels, frame count, frames ignored as
+    /// lighting changes.
+    events: fs::File,
+    /// One tab-separated line per tripwire crossing: time, tripwire,
+    /// direction, track id, running count for that tripwire and direction.
+    crossings: Option<fs::File>,
+    /// One tab-separated line per tamper: detection time, kind, when it
+    /// started, edge energy and its usual level, fraction of edges changed.
+    tamper: Option<fs::File>,
+}
+
+struct HeatmapOutput {
+    heatmap: Heatmap,
+    /// Path without the `.pgm` or `.webp` extension.
+    filename: String,
+    next_write: Option<time::Timespec>,
+}
+
+impl HeatmapOutput {
+    fn write(&self, config: &PipelineConfig) -> io::Result<()> {
+        let mut pgm = Vec::new();
+        try!(self.heatmap.write_pgm(&mut pgm));
+        try!(heatmap::replace_file(&format!("{}.pgm", self.filename), &pgm));
+        match self.heatmap.encode_webp(&config.encoder) {
+            Ok(webp) => try!(heatmap::replace_file(&format!("{}.webp", self.filename), &webp)),
+            Err(err) => println!("[{}] skipping heatmap WebP: {}", config.name, err),
+        }
+        println!("[{}] wrote heatmap over {} frames", config.name, self.heatmap.frames());
+        Ok(())
+    }
+}
+
+// `i` is the index of the newest captured frame, not of the (older) frame
+// being emitted.
+fn handle_motion_output(
+    config: &PipelineConfig,
+    frameout_fs: &mut Option<FullsizeOutput>,
+    i: u64,
+    output: MotionOutput,
+)
+    -> io::Result<()>
+{
+    match output {
+        MotionOutput::Frame { event_id, when, surface, blobs, tracks } => {
+            if let Some(ref mut frameout_fs) = *frameout_fs {
+                let pending = PendingFrame {
+                    index: i,
+                    event_id: event_id,
+                    when: when,
+                    blobs: blobs,
+                    tracks: tracks,
+                };
+                try!(frameout_fs.pool.submit(pending, surface));
+            }
+        }
+        MotionOutput::EventEnd(event) => {
+            println!("[{}] event #{} ended: {}.{:09} to {}.{:09}, {} frames, peak {} lit px, {} lighting changes",
+                config.name, event.id, event.start.sec, event.start.nsec,
+                event.end.sec, event.end.nsec, event.frame_count, event.peak_lit_pixels,
+                event.illumination_changes);
+
+            if let Some(ref mut frameout_fs) = *frameout_fs {
+                try!(writeln!(frameout_fs.events, "{}\t{}.{:09}\t{}.{:09}\t{}.{:09}\t{}\t{}\t{}",
+                    event.id,
+                    event.triggered_at.sec, event.triggered_at.nsec,
+                    event.start.sec, event.start.nsec,
+                    event.end.sec, event.end.nsec,
+                    event.peak_lit_pixels, event.frame_count, event.illumination_changes));
+            }
+        }
+        MotionOutput::Crossing(crossing) => {
+            println!("[{}] track #{} crossed {} {} @{}.{:09} ({} so far)",
+                config.name, crossing.track_id, crossing.tripwire, crossing.direction,
+                crossing.when.sec, crossing.when.nsec, crossing.count);
+
+            if let Some(FullsizeOutput { crossings: Some(ref mut crossings), .. }) = *frameout_fs {
+                try!(writeln!(crossings, "{}.{:09}\t{}\t{}\t{}\t{}",
+                    crossing.when.sec, crossing.when.nsec, crossing.tripwire,
+                    crossing.direction, crossing.track_id, crossing.count));
+            }
+        }
+        MotionOutput::Tamper(tamper) => {
+            println!("[{}] TAMPER: camera {} since {}.{:09} (edge energy {:.1}, usually {:.1}; {:.0}% of edges changed)",
+                config.name, tamper.kind, tamper.since.sec, tamper.since.nsec,
+                tamper.edge_energy, tamper.baseline_energy, 100.0 * tamper.changed_fraction);
+
+            if let Some(FullsizeOutput { tamper: Some(ref mut log), .. }) = *frameout_fs {
+                try!(writeln!(log, "{}.{:09}\t{}\t{}.{:09}\t{:.2}\t{:.2}\t{:.3}",
+                    tamper.detected_at.sec, tamper.detected_at.nsec, tamper.kind,
+                    tamper.since.sec, tamper.since.nsec,
+                    tamper.edge_energy, tamper.baseline_energy, tamper.changed_fraction));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Writes frames the encode pool has finished, which arrive in the order
+// they were submitted.
+fn write_encoded(
+    config: &PipelineConfig,
+    status: &PipelineStatus,
+    frameout_fs: &mut FullsizeOutput,
+    encoded: Vec<Encoded<PendingFrame>>,
+)
+    -> io::Result<()>
+{
+    for Encoded { meta: frame, webp, elapsed } in encoded {
+        let webp = match webp {
+            Ok(webp) => webp,
+            Err(err) => {
+                println!("[{}] skipping F#{:010} @{}.{:09}: {}",
+                    config.name, frame.index, frame.when.sec, frame.when.nsec, err);
+                continue;
+            }
+        };
+        println!("[{}] transcode time: {}", config.name, elapsed);
+
+        try!(fwebp::write_frame(&mut frameout_fs.frames, frame.when, Some(frame.event_id), &webp[..]));
+        status.frame_emitted();
+
+        println!("[{}] emit F#{:010} @{}.{:09} event={} len={} blobs={} tracks={}",
+            config.name, frame.index, frame.when.sec, frame.when.nsec, frame.event_id,
+            webp.len(), frame.blobs.len(), frame.tracks.len());
+        for blob in frame.blobs.iter() {
+            println!("[{}]   blob area={} centroid=({:.1},{:.1}) bbox={}x{}+{}+{}",
+                config.name, blob.area, blob.centroid.0, blob.centroid.1,
+                blob.bbox.width(), blob.bbox.height(), blob.bbox.x0, blob.bbox.y0);
+        }
+        for track in frame.tracks.iter() {
+            println!("[{}]   track #{} at ({:.1},{:.1}) moving ({:.1},{:.1}) px/s, {} frames over {}",
+                config.name, track.id, track.centroid.0, track.centroid.1,
+                track.velocity.0, track.velocity.1, track.hits, track.lifetime());
+        }
+        frameout_fs.pool.recycle(webp);
+    }
+    Ok(())
+}
+
+// cargo run --release | mpv /dev/stdin --demuxer=rawvideo --demuxer-rawvideo=w=1280:h=960
+// ffmpeg -f rawvideo -video_size 1280x960 -framerate 5 /dev/stdin foo.webm
+
+fn write_lumasurface_yuv420p<W: Write>(wri: &mut W, surf: &Surface<Luma, u8, Box<[u8]>>) -> io::Result<()> {
+    let (width, height) = (surf.width() as usize, surf.height() as usize);
+
+    let chroma_hack = vec![0x80; width * height / 4];
+    try!(wri.write_all(surf.raw_bytes()));
+    try!(wri.write_all(&chroma_hack[..]));
+    try!(wri.write_all(&chroma_hack[..]));
+
+    Ok(())
+}
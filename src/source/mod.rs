@@ -0,0 +1,58 @@
+use std::io;
+use std::thread;
+
+use time::{self, Duration, Timespec};
+use surface::{Surface, Yuv422};
+
+mod v4l2;
+mod replay;
+mod pattern;
+
+pub use self::v4l2::V4l2Source;
+pub use self::replay::ReplaySource;
+pub use self::pattern::PatternSource;
+
+pub const FOURCC_YUYV: [u8; 4] = *b"YUYV";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FrameFormat {
+    pub width: u32,
+    pub height: u32,
+    pub fourcc: [u8; 4],
+}
+
+pub struct CapturedFrame {
+    pub when: Timespec,
+    pub surface: Surface<Yuv422, u8, Box<[u8]>>,
+}
+
+/// Anything the capture thread can pull frames from.
+///
+/// Every source currently hands out interleaved YUYV, which is what the
+/// rest of the pipeline consumes.
+pub trait FrameSource: Send {
+    fn format(&self) -> FrameFormat;
+
+    /// Blocks until the next frame is ready. `Ok(None)` means the source
+    /// is exhausted and the pipeline should wind down.
+    fn next_frame(&mut self) -> io::Result<Option<CapturedFrame>>;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pacing {
+    /// Emit frames as fast as they can be produced.
+    FullSpeed,
+    /// Sleep between frames so they arrive at the nominal frame interval.
+    RealTime,
+}
+
+impl Pacing {
+    fn wait_until(&self, when: Timespec) {
+        if *self == Pacing::RealTime {
+            let wait = when - time::get_time();
+            if wait > Duration::zero() {
+                thread::sleep(wait.to_std().unwrap());
+            }
+        }
+    }
+}
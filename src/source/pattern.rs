@@ -0,0 +1,92 @@
+use std::io;
+
+use time::{self, Duration, Timespec};
+use surface::{Surface, Yuv422};
+
+use super::{FrameSource, FrameFormat, CapturedFrame, Pacing, FOURCC_YUYV};
+
+/// Synthetic frames: a static luma gradient with a bright square bouncing
+/// across it, so the motion stage has something to trigger on.
+pub struct PatternSource {
+    width: u32,
+    height: u32,
+    interval: Duration,
+    pacing: Pacing,
+    started: Timespec,
+    frame_index: u64,
+    frame_limit: Option<u64>,
+}
+
+impl PatternSource {
+    pub fn new(width: u32, height: u32, interval: Duration, pacing: Pacing) -> PatternSource {
+        PatternSource {
+            width: width,
+            height: height,
+            interval: interval,
+            pacing: pacing,
+            started: time::get_time(),
+            frame_index: 0,
+            frame_limit: None,
+        }
+    }
+
+    /// Stop after `limit` frames instead of running forever.
+    pub fn with_frame_limit(mut self, limit: u64) -> PatternSource {
+        self.frame_limit = Some(limit);
+        self
+    }
+
+    fn render(&self, surf: &mut Surface<Yuv422, u8, Box<[u8]>>) {
+        let (width, height) = (self.width as usize, self.height as usize);
+        // no bigger than the frame, however small it is.
+        let side = ::std::cmp::min(::std::cmp::max(8, height / 8), ::std::cmp::min(width, height));
+
+        // triangle wave so the square bounces between the edges.
+        let span = 2 * (width.saturating_sub(side) + 1) as u64;
+        let phase = (self.frame_index * 8) % ::std::cmp::max(span, 1);
+        let box_x = (if phase < span / 2 { phase } else { span - phase }) as usize;
+        let box_y = (height - side) / 2;
+
+        let bytes = surf.raw_bytes_mut();
+        for y in 0..height {
+            let row = &mut bytes[y * width * 2..][..width * 2];
+            for x in 0..width {
+                let inside = box_x <= x && x < box_x + side && box_y <= y && y < box_y + side;
+                let luma = if inside { 0xEB } else { (0x10 + x * 0x80 / width) as u8 };
+
+                row[2 * x] = luma;
+                row[2 * x + 1] = 0x80;
+            }
+        }
+    }
+}
+
+impl FrameSource for PatternSource {
+    fn format(&self) -> FrameFormat {
+        FrameFormat {
+            width: self.width,
+            height: self.height,
+            fourcc: FOURCC_YUYV,
+        }
+    }
+
+    fn next_frame(&mut self) -> io::Result<Option<CapturedFrame>> {
+        if let Some(limit) = self.frame_limit {
+            if limit <= self.frame_index {
+                return Ok(None);
+            }
+        }
+
+        let frame_when = self.started + self.interval * self.frame_index as i32;
+        self.pacing.wait_until(frame_when);
+
+        let mut surf = Surface::<Yuv422, u8, _>::new_black(self.width, self.height);
+        self.render(&mut surf);
+        self.frame_index += 1;
+
+        Ok(Some(CapturedFrame {
+            when: frame_when,
+            surface: surf,
+        }))
+    }
+}
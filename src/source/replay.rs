@@ -0,0 +1,90 @@
+use std::io::{self, Read};
+
+use time::{self, Duration, Timespec};
+use surface::{Surface, Yuv422p, Yuv422};
+
+use super::{FrameSource, FrameFormat, CapturedFrame, Pacing, FOURCC_YUYV};
+use conversions::yuv422p_to_yuyv_interleave;
+
+/// Reads back the raw planar `.yuv422p` files written by camcap.
+///
+/// The files carry no timestamps, so each frame is stamped with
+/// `started + index * interval`, which keeps timing-dependent motion
+/// logic deterministic regardless of pacing.
+pub struct ReplaySource<R> {
+    inner: R,
+    width: u32,
+    height: u32,
+    interval: Duration,
+    pacing: Pacing,
+    started: Timespec,
+    frame_index: u64,
+    planar: Surface<Yuv422p, u8, Box<[u8]>>,
+}
+
+impl<R: Read> ReplaySource<R> {
+    pub fn new(inner: R, width: u32, height: u32, interval: Duration, pacing: Pacing) -> ReplaySource<R> {
+        ReplaySource {
+            inner: inner,
+            width: width,
+            height: height,
+            interval: interval,
+            pacing: pacing,
+            started: time::get_time(),
+            frame_index: 0,
+            planar: Surface::new_black(width, height),
+        }
+    }
+}
+
+impl<R: Read + Send> FrameSource for ReplaySource<R> {
+    fn format(&self) -> FrameFormat {
+        FrameFormat {
+            width: self.width,
+            height: self.height,
+            fourcc: FOURCC_YUYV,
+        }
+    }
+
+    /// Frames are converted back to interleaved YUYV, as the camera would
+    /// have produced them.
+    fn next_frame(&mut self) -> io::Result<Option<CapturedFrame>> {
+        loop {
+            if !try!(read_full(&mut self.inner, self.planar.raw_bytes_mut())) {
+                return Ok(None);
+            }
+
+            let frame_when = self.started + self.interval * self.frame_index as i32;
+            self.frame_index += 1;
+
+            // PunchCat deallocates the head of the file as it grows, and
+            // those holes read back as zeros.
+            if self.planar.raw_bytes().iter().all(|px| *px == 0) {
+                continue;
+            }
+
+            self.pacing.wait_until(frame_when);
+
+            let mut surf = Surface::<Yuv422, u8, _>::new_black(self.width, self.height);
+            yuv422p_to_yuyv_interleave(&self.planar, &mut surf);
+            return Ok(Some(CapturedFrame {
+                when: frame_when,
+                surface: surf,
+            }));
+        }
+    }
+}
+
+// Fills `buf` completely. A trailing partial frame is treated as end of file.
+fn read_full<R: Read>(rdr: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match rdr.read(&mut buf[filled..]) {
+            Ok(0) => return Ok(false),
+            Ok(length) => filled += length,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(true)
+}
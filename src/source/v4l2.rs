@@ -0,0 +1,58 @@
+use std::io;
+
+use rscam;
+use time;
+use surface::{Surface, Yuv422};
+
+use super::{FrameSource, FrameFormat, CapturedFrame, FOURCC_YUYV};
+
+pub struct V4l2Source {
+    camera: rscam::Camera,
+    width: u32,
+    height: u32,
+}
+
+impl V4l2Source {
+    pub fn open(device: &str, width: u32, height: u32, interval: (u32, u32)) -> io::Result<V4l2Source> {
+        let mut camera = try!(rscam::new(device));
+
+        let started = camera.start(&rscam::Config {
+            interval: interval,
+            resolution: (width, height),
+            format: &FOURCC_YUYV,
+            ..Default::default()
+        });
+        match started {
+            Ok(()) => (),
+            Err(rscam::Error::Io(err)) => return Err(err),
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("{} rejected capture config: {:?}", device, err))),
+        }
+
+        Ok(V4l2Source {
+            camera: camera,
+            width: width,
+            height: height,
+        })
+    }
+}
+
+impl FrameSource for V4l2Source {
+    fn format(&self) -> FrameFormat {
+        FrameFormat {
+            width: self.width,
+            height: self.height,
+            fourcc: FOURCC_YUYV,
+        }
+    }
+
+    fn next_frame(&mut self) -> io::Result<Option<CapturedFrame>> {
+        let frame_when = time::get_time();
+
+        let frame_data = try!(self.camera.capture()).to_vec().into_boxed_slice();
+        Ok(Some(CapturedFrame {
+            when: frame_when,
+            surface: Surface::<Yuv422, u8, _>::new(self.width, self.height, frame_data),
+        }))
+    }
+}
@@ -0,0 +1,196 @@
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use time::{self, Timespec};
+
+use pipeline::{self, PipelineConfig, SourceKind};
+
+const BACKOFF_MIN_SECS: u64 = 1;
+const BACKOFF_MAX_SECS: u64 = 60;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum State {
+    Starting,
+    Running,
+    /// Waiting to restart after a failure.
+    Backoff,
+    /// The source ran out of frames.
+    Finished,
+    /// Gave up; the source isn't one that can be reopened.
+    Failed,
+}
+
+#[derive(Clone, Debug)]
+pub struct StatusSnapshot {
+    pub state: State,
+    pub frames_captured: u64,
+    pub frames_emitted: u64,
+    pub last_frame: Option<Timespec>,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+/// Per-camera counters, shared between a pipeline and the supervisor.
+pub struct PipelineStatus {
+    inner: Mutex<StatusSnapshot>,
+}
+
+impl PipelineStatus {
+    pub fn new() -> PipelineStatus {
+        PipelineStatus {
+            inner: Mutex::new(StatusSnapshot {
+                state: State::Starting,
+                frames_captured: 0,
+                frames_emitted: 0,
+                last_frame: None,
+                restarts: 0,
+                last_error: None,
+            }),
+        }
+    }
+
+    pub fn snapshot(&self) -> StatusSnapshot {
+        self.inner.lock().unwrap().clone()
+    }
+
+    pub fn set_state(&self, state: State) {
+        self.inner.lock().unwrap().state = state;
+    }
+
+    pub fn frame_captured(&self, when: Timespec) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = State::Running;
+        inner.frames_captured += 1;
+        inner.last_frame = Some(when);
+    }
+
+    pub fn frame_emitted(&self) {
+        self.inner.lock().unwrap().frames_emitted += 1;
+    }
+
+    fn record_failure(&self, message: String, state: State) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = state;
+        inner.last_error = Some(message);
+    }
+
+    fn record_restart(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = State::Starting;
+        inner.restarts += 1;
+    }
+}
+
+/// Runs every pipeline on its own thread until they've all finished or
+/// failed for good. A pipeline erroring or panicking only affects its own
+/// camera; live devices are reopened with exponential backoff.
+///
+/// Returns false if any pipeline ended in failure.
+pub fn run(configs: Vec<PipelineConfig>, status_interval: Option<Duration>) -> bool {
+    let (done_tx, done_rx) = channel();
+
+    let mut cameras = Vec::new();
+    for (index, config) in configs.into_iter().enumerate() {
+        let name = config.name.clone();
+        let status = Arc::new(PipelineStatus::new());
+        let worker_status = status.clone();
+        let worker_done_tx = done_tx.clone();
+
+        let spawned = thread::Builder::new()
+            .name(name.clone())
+            .spawn(move || {
+                supervise(config, &worker_status);
+                let _ = worker_done_tx.send(index);
+            });
+
+        if let Err(err) = spawned {
+            println!("[{}] failed to start: {}", name, err);
+            status.record_failure(err.to_string(), State::Failed);
+            let _ = done_tx.send(index);
+        }
+        cameras.push((name, status));
+    }
+    drop(done_tx);
+
+    let mut remaining = cameras.len();
+    while 0 < remaining {
+        let received = match status_interval {
+            Some(interval) => done_rx.recv_timeout(interval),
+            None => done_rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(index) => {
+                remaining -= 1;
+                let (ref name, ref status) = cameras[index];
+                report(name, &status.snapshot());
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                for &(ref name, ref status) in cameras.iter() {
+                    report(name, &status.snapshot());
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    cameras.iter().all(|&(_, ref status)| status.snapshot().state != State::Failed)
+}
+
+fn supervise(config: PipelineConfig, status: &PipelineStatus) {
+    let restartable = match config.source {
+        SourceKind::Device(_) => true,
+        SourceKind::Replay(_) | SourceKind::Pattern => false,
+    };
+
+    let mut backoff_secs = BACKOFF_MIN_SECS;
+    loop {
+        let captured_before = status.snapshot().frames_captured;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pipeline::run(config.clone(), status)
+        }));
+
+        let message = match result {
+            Ok(Ok(())) => {
+                status.set_state(State::Finished);
+                return;
+            }
+            Ok(Err(err)) => err.to_string(),
+            Err(_) => "pipeline panicked".to_string(),
+        };
+
+        if !restartable {
+            println!("[{}] failed: {}", config.name, message);
+            status.record_failure(message, State::Failed);
+            return;
+        }
+
+        // a pipeline that got frames through was healthy for a while, so
+        // don't hold its earlier failures against it.
+        if captured_before < status.snapshot().frames_captured {
+            backoff_secs = BACKOFF_MIN_SECS;
+        }
+
+        println!("[{}] failed: {}; restarting in {}s", config.name, message, backoff_secs);
+        status.record_failure(message, State::Backoff);
+        thread::sleep(Duration::from_secs(backoff_secs));
+        backoff_secs = cmp::min(2 * backoff_secs, BACKOFF_MAX_SECS);
+        status.record_restart();
+    }
+}
+
+fn report(name: &str, snap: &StatusSnapshot) {
+    let last_frame_age = match snap.last_frame {
+        Some(when) => format!("{}s ago", (time::get_time() - when).num_seconds()),
+        None => "never".to_string(),
+    };
+    let mut line = format!("[{}] status: {:?}, captured={} emitted={} restarts={} last frame {}",
+        name, snap.state, snap.frames_captured, snap.frames_emitted, snap.restarts, last_frame_age);
+    if let Some(ref err) = snap.last_error {
+        line.push_str(&format!(", last error: {}", err));
+    }
+    println!("{}", line);
+}
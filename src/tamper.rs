@@ -0,0 +1,238 @@
+use std::fmt;
+
+use time::{Duration, Timespec};
+
+#[derive(Clone, Copy, Debug)]
+pub struct TamperConfig {
+    /// Edge energy below this fraction of its usual level means the lens is
+    /// covered or out of focus.
+    pub energy_drop: f32,
+    /// When this fraction of the scene's edges no longer match the reference
+    /// view, the camera has been moved.
+    pub change_fraction: f32,
+    /// How long either condition must last before it's reported, and how
+    /// long after the first frame the scene is learnt before either is
+    /// looked for.
+    pub hold: Duration,
+    /// How long the camera may stay obscured before its darkened edge
+    /// energy is taken as the new normal, so lights going out for the night
+    /// don't keep motion detection off until morning. The scene is still
+    /// compared against how it looked before once the light comes back.
+    pub relearn_after: Duration,
+}
+
+impl Default for TamperConfig {
+    fn default() -> TamperConfig {
+        TamperConfig {
+            energy_drop: 0.3,
+            change_fraction: 0.6,
+            hold: Duration::seconds(10),
+            relearn_after: Duration::minutes(10),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TamperKind {
+    /// Covered, defocused or blinded: the scene has lost its edges.
+    Obscured,
+    /// Turned or repositioned: the edges are all somewhere new.
+    Moved,
+}
+
+impl fmt::Display for TamperKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            TamperKind::Obscured => "obscured",
+            TamperKind::Moved => "moved",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TamperEvent {
+    pub kind: TamperKind,
+    /// Capture time of the first frame showing the condition.
+    pub since: Timespec,
+    /// Capture time of the frame that confirmed it.
+    pub detected_at: Timespec,
+    /// Mean edge energy then, and what it usually is.
+    pub edge_energy: f32,
+    pub baseline_energy: f32,
+    /// Fraction of the scene's edges that differed from the reference view.
+    pub changed_fraction: f32,
+}
+
+// Edge strength that counts as an edge when comparing against the reference.
+const EDGE_LEVEL: f32 = 64.0;
+// Weight of each normal frame in the baseline energy and reference view.
+const BASELINE_RATE: f32 = 0.01;
+
+/// Watches the edge map of every frame for the camera being covered,
+/// defocused or moved.
+pub struct TamperDetector {
+    config: TamperConfig,
+    /// Capture time of the first frame. Cameras often take a while to
+    /// settle their exposure, so until `hold` after it the detector only
+    /// learns the scene.
+    started: Option<Timespec>,
+    baseline_energy: Option<f32>,
+    reference: Option<Vec<f32>>,
+    /// The baseline energy from before an obscured view was relearnt, until
+    /// the energy climbs back towards it. Meanwhile the reference is kept
+    /// as it was and nothing is judged moved against it.
+    dimmed_from: Option<f32>,
+    obscured_since: Option<Timespec>,
+    moved_since: Option<Timespec>,
+    active: Option<TamperKind>,
+}
+
+impl TamperDetector {
+    pub fn new(config: TamperConfig) -> TamperDetector {
+        TamperDetector {
+            config: config,
+            started: None,
+            baseline_energy: None,
+            reference: None,
+            dimmed_from: None,
+            obscured_since: None,
+            moved_since: None,
+            active: None,
+        }
+    }
+
+    /// Whether a tamper has been reported and hasn't cleared yet.
+    pub fn active(&self) -> Option<TamperKind> {
+        self.active
+    }
+
+    /// Takes the edge map of the frame captured at `when` and returns a
+    /// tamper the first time one has lasted `hold`.
+    pub fn update(&mut self, when: Timespec, edge: &[u8]) -> Option<TamperEvent> {
+        let energy = edge.iter().map(|px| *px as u64).sum::<u64>() as f32 / edge.len() as f32;
+        let started = *self.started.get_or_insert(when);
+        if when - started < self.config.hold {
+            // track the settling view as it is rather than averaging it in.
+            self.rebaseline(energy, edge);
+            return None;
+        }
+
+        if let Some(lit) = self.dimmed_from {
+            if self.config.energy_drop * lit <= energy {
+                self.dimmed_from = None;
+                self.baseline_energy = Some(lit);
+            }
+        }
+
+        let baseline = *self.baseline_energy.get_or_insert(energy);
+        let changed_fraction = match self.reference {
+            // a dim view has lost too many edges to compare with a lit one.
+            Some(ref reference) if self.dimmed_from.is_none() => changed_fraction(reference, edge),
+            _ => 0.0,
+        };
+
+        let obscured = energy < self.config.energy_drop * baseline;
+        let moved = !obscured && self.config.change_fraction <= changed_fraction;
+        track_condition(&mut self.obscured_since, obscured, when);
+        track_condition(&mut self.moved_since, moved, when);
+
+        if !obscured && !moved {
+            self.active = None;
+            self.learn(energy, edge);
+            return None;
+        }
+
+        let (kind, since) = match (self.obscured_since, self.moved_since) {
+            (Some(since), _) => (TamperKind::Obscured, since),
+            (None, Some(since)) => (TamperKind::Moved, since),
+            (None, None) => unreachable!(),
+        };
+        if kind == TamperKind::Obscured && self.config.hold + self.config.relearn_after <= when - since {
+            // this little energy is normal now, but keep the lit scene to
+            // check the view against when the light comes back.
+            if self.dimmed_from.is_none() {
+                self.dimmed_from = Some(baseline);
+            }
+            self.baseline_energy = Some(energy);
+            self.obscured_since = None;
+            self.active = None;
+            return None;
+        }
+        if when - since < self.config.hold || self.active == Some(kind) {
+            return None;
+        }
+
+        self.active = Some(kind);
+        let event = TamperEvent {
+            kind: kind,
+            since: since,
+            detected_at: when,
+            edge_energy: energy,
+            baseline_energy: baseline,
+            changed_fraction: changed_fraction,
+        };
+        if kind == TamperKind::Moved {
+            // the new view is the scene from now on.
+            self.rebaseline(energy, edge);
+            self.moved_since = None;
+        }
+        Some(event)
+    }
+
+    fn rebaseline(&mut self, energy: f32, edge: &[u8]) {
+        self.baseline_energy = Some(energy);
+        match self.reference {
+            Some(ref mut reference) => {
+                for (r, px) in reference.iter_mut().zip(edge.iter()) {
+                    *r = *px as f32;
+                }
+            }
+            None => self.reference = Some(edge.iter().map(|px| *px as f32).collect()),
+        }
+    }
+
+    fn learn(&mut self, energy: f32, edge: &[u8]) {
+        if let Some(ref mut baseline) = self.baseline_energy {
+            *baseline += BASELINE_RATE * (energy - *baseline);
+        }
+        if self.dimmed_from.is_some() {
+            return;
+        }
+        match self.reference {
+            Some(ref mut reference) => {
+                for (r, px) in reference.iter_mut().zip(edge.iter()) {
+                    *r += BASELINE_RATE * (*px as f32 - *r);
+                }
+            }
+            None => self.reference = Some(edge.iter().map(|px| *px as f32).collect()),
+        }
+    }
+}
+
+fn track_condition(since: &mut Option<Timespec>, holds: bool, when: Timespec) {
+    if !holds {
+        *since = None;
+    } else if since.is_none() {
+        *since = Some(when);
+    }
+}
+
+// Of the pixels that are an edge in either view, the fraction that are an
+// edge in only one.
+fn changed_fraction(reference: &[f32], edge: &[u8]) -> f32 {
+    let (mut edges, mut changed) = (0u64, 0u64);
+    for (r, px) in reference.iter().zip(edge.iter()) {
+        let (was, is) = (EDGE_LEVEL < *r, EDGE_LEVEL < *px as f32);
+        if was || is {
+            edges += 1;
+            if was != is {
+                changed += 1;
+            }
+        }
+    }
+    if edges == 0 {
+        0.0
+    } else {
+        changed as f32 / edges as f32
+    }
+}
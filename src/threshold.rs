@@ -0,0 +1,73 @@
+/// Derives the lit-pixel threshold from the detector's noise instead of
+/// using a fixed `edge_threshold`.
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveThreshold {
+    /// Fraction of watched pixels, 0 to 1, taken to be noise each frame.
+    pub percentile: f32,
+    /// Added to the noise floor to get the threshold.
+    pub margin: u8,
+    pub min: u8,
+    pub max: u8,
+}
+
+impl Default for AdaptiveThreshold {
+    fn default() -> AdaptiveThreshold {
+        AdaptiveThreshold {
+            percentile: 0.95,
+            margin: 0x20,
+            min: 0x30,
+            max: 0xc0,
+        }
+    }
+}
+
+// Weight of each new frame's percentile in the running noise floor.
+const FLOOR_RATE: f32 = 0.05;
+
+/// A running estimate of how bright the detector output gets from noise
+/// alone.
+pub struct NoiseFloor {
+    config: AdaptiveThreshold,
+    floor: Option<f32>,
+}
+
+impl NoiseFloor {
+    pub fn new(config: AdaptiveThreshold) -> NoiseFloor {
+        NoiseFloor {
+            config: config,
+            floor: None,
+        }
+    }
+
+    /// Folds in a histogram of one frame's detector output.
+    pub fn update(&mut self, histogram: &[u32; 256]) {
+        let total: u64 = histogram.iter().map(|n| *n as u64).sum();
+        if total == 0 {
+            return;
+        }
+
+        let wanted = (self.config.percentile as f64 * total as f64).ceil() as u64;
+        let mut seen = 0;
+        let mut level = 0;
+        for (value, count) in histogram.iter().enumerate() {
+            seen += *count as u64;
+            level = value;
+            if wanted <= seen {
+                break;
+            }
+        }
+
+        let level = level as f32;
+        self.floor = Some(match self.floor {
+            Some(floor) => floor + FLOOR_RATE * (level - floor),
+            None => level,
+        });
+    }
+
+    /// The current threshold, between the configured clamps.
+    pub fn threshold(&self) -> u8 {
+        let floor = self.floor.unwrap_or(0.0);
+        let raw = floor + self.config.margin as f32;
+        raw.max(self.config.min as f32).min(self.config.max as f32) as u8
+    }
+}
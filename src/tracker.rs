@@ -0,0 +1,250 @@
+use time::{Duration, Timespec};
+
+use blob::{Blob, BoundingBox};
+
+// Blobs matched per frame. Noise can break a frame into thousands of
+// specks, and matching is quadratic in them, so beyond this only the
+// largest are followed.
+const MAX_BLOBS: usize = 256;
+
+/// One moving region followed across frames.
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub id: u64,
+    pub bbox: BoundingBox,
+    pub centroid: (f32, f32),
+    /// Where the centroid was when the track was last matched before this,
+    /// if it has been matched more than once.
+    pub prev_centroid: Option<(f32, f32)>,
+    /// Pixels per second, smoothed over the frames the track was seen in.
+    pub velocity: (f32, f32),
+    pub area: usize,
+    pub first_seen: Timespec,
+    pub last_seen: Timespec,
+    /// Frames the track has been matched in.
+    pub hits: u32,
+    /// Consecutive frames it has gone unmatched.
+    pub misses: u32,
+}
+
+impl Track {
+    pub fn lifetime(&self) -> Duration {
+        self.last_seen - self.first_seen
+    }
+
+    // Where the centroid should be at `when` if it kept its velocity.
+    fn predicted(&self, when: Timespec) -> (f32, f32) {
+        let dt = seconds(when - self.last_seen);
+        (self.centroid.0 + self.velocity.0 * dt, self.centroid.1 + self.velocity.1 * dt)
+    }
+}
+
+/// Associates each frame's blobs with the tracks from earlier frames.
+///
+/// Blobs are matched greedily, overlapping boxes first and then by
+/// distance from each track's predicted centroid. Blobs left over start new
+/// tracks; tracks left over are dropped once they've been missed for more
+/// than `max_missed` frames in a row.
+pub struct Tracker {
+    tracks: Vec<Track>,
+    next_id: u64,
+    max_distance: f32,
+    max_missed: u32,
+}
+
+impl Tracker {
+    pub fn new(max_distance: f32, max_missed: u32) -> Tracker {
+        Tracker {
+            tracks: Vec::new(),
+            next_id: 1,
+            max_distance: max_distance,
+            max_missed: max_missed,
+        }
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    /// Matches the blobs found in the frame captured at `when`, returning
+    /// the id of the track each one was assigned to, in the same order.
+    ///
+    /// `blobs` should be sorted largest first: only the first 256 are
+    /// tracked, and the ids returned stop there.
+    pub fn update(&mut self, when: Timespec, blobs: &[Blob]) -> Vec<u64> {
+        let blobs = &blobs[..::std::cmp::min(blobs.len(), MAX_BLOBS)];
+
+        // (overlap, distance, track index, blob index) for every plausible pair.
+        let mut pairs = Vec::new();
+        for (ti, track) in self.tracks.iter().enumerate() {
+            let (px, py) = track.predicted(when);
+            for (bi, blob) in blobs.iter().enumerate() {
+                let iou = track.bbox.iou(&blob.bbox);
+                let (dx, dy) = (blob.centroid.0 - px, blob.centroid.1 - py);
+                let distance = (dx * dx + dy * dy).sqrt();
+                if 0.0 < iou || distance <= self.max_distance {
+                    pairs.push((iou, distance, ti, bi));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| {
+            b.0.partial_cmp(&a.0).unwrap()
+                .then(a.1.partial_cmp(&b.1).unwrap())
+        });
+
+        let mut track_matched = vec![false; self.tracks.len()];
+        let mut assigned = vec![None; blobs.len()];
+        for &(_, _, ti, bi) in pairs.iter() {
+            if track_matched[ti] || assigned[bi].is_some() {
+                continue;
+            }
+            track_matched[ti] = true;
+            assigned[bi] = Some(self.tracks[ti].id);
+            observe(&mut self.tracks[ti], when, &blobs[bi]);
+        }
+
+        for (track, matched) in self.tracks.iter_mut().zip(track_matched.iter()) {
+            if !*matched {
+                track.misses += 1;
+            }
+        }
+        let max_missed = self.max_missed;
+        self.tracks.retain(|t| t.misses <= max_missed);
+
+        let mut ids = Vec::with_capacity(blobs.len());
+        for (blob, assigned) in blobs.iter().zip(assigned.into_iter()) {
+            let id = match assigned {
+                Some(id) => id,
+                None => {
+                    let id = self.next_id;
+                    self.next_id += 1;
+                    self.tracks.push(Track {
+                        id: id,
+                        bbox: blob.bbox,
+                        centroid: blob.centroid,
+                        prev_centroid: None,
+                        velocity: (0.0, 0.0),
+                        area: blob.area,
+                        first_seen: when,
+                        last_seen: when,
+                        hits: 1,
+                        misses: 0,
+                    });
+                    id
+                }
+            };
+            ids.push(id);
+        }
+        ids
+    }
+}
+
+fn observe(track: &mut Track, when: Timespec, blob: &Blob) {
+    let dt = seconds(when - track.last_seen);
+    if 0.0 < dt {
+        let vx = (blob.centroid.0 - track.centroid.0) / dt;
+        let vy = (blob.centroid.1 - track.centroid.1) / dt;
+        track.velocity = if track.hits == 1 {
+            (vx, vy)
+        } else {
+            (0.5 * (track.velocity.0 + vx), 0.5 * (track.velocity.1 + vy))
+        };
+    }
+
+    track.prev_centroid = Some(track.centroid);
+    track.centroid = blob.centroid;
+    track.bbox = blob.bbox;
+    track.area = blob.area;
+    track.last_seen = when;
+    track.hits += 1;
+    track.misses = 0;
+}
+
+fn seconds(d: Duration) -> f32 {
+    match d.num_nanoseconds() {
+        Some(ns) => ns as f32 / 1e9,
+        None => d.num_seconds() as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Timespec;
+
+    use blob::{Blob, BoundingBox};
+
+    use super::Tracker;
+
+    fn blob(x0: u32, y0: u32, width: u32, height: u32) -> Blob {
+        Blob {
+            area: (width * height) as usize,
+            centroid: (x0 as f32 + width as f32 / 2.0, y0 as f32 + height as f32 / 2.0),
+            bbox: BoundingBox { x0: x0, y0: y0, x1: x0 + width, y1: y0 + height },
+        }
+    }
+
+    fn at(sec: i64) -> Timespec {
+        Timespec::new(sec, 0)
+    }
+
+    #[test]
+    fn overlapping_blob_continues_a_track() {
+        let mut tracker = Tracker::new(8.0, 2);
+        let first = tracker.update(at(0), &[blob(10, 10, 20, 20)]);
+        let second = tracker.update(at(1), &[blob(14, 10, 20, 20)]);
+        assert_eq!(first, second);
+
+        let track = &tracker.tracks()[0];
+        assert_eq!(track.hits, 2);
+        assert_eq!(track.prev_centroid, Some((20.0, 20.0)));
+        assert_eq!(track.velocity, (4.0, 0.0));
+    }
+
+    #[test]
+    fn overlap_wins_over_distance() {
+        let mut tracker = Tracker::new(100.0, 2);
+        let ids = tracker.update(at(0), &[blob(0, 0, 40, 10), blob(50, 0, 4, 4)]);
+        // centred nearer the small track, but overlapping the wide one.
+        let next = tracker.update(at(1), &[blob(36, 0, 4, 10)]);
+        assert_eq!(next, vec![ids[0]]);
+    }
+
+    #[test]
+    fn nearby_blob_continues_a_track_without_overlap() {
+        let mut tracker = Tracker::new(16.0, 2);
+        let first = tracker.update(at(0), &[blob(0, 0, 4, 4)]);
+        let second = tracker.update(at(1), &[blob(10, 0, 4, 4)]);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn distance_is_measured_from_the_predicted_position() {
+        let mut tracker = Tracker::new(4.0, 2);
+        let first = tracker.update(at(0), &[blob(0, 0, 4, 4)]);
+        tracker.update(at(1), &[blob(3, 0, 4, 4)]);
+        // 10 pixels on from the last blob, but where it was heading.
+        let third = tracker.update(at(3), &[blob(9, 0, 4, 4)]);
+        assert_eq!(first, third);
+    }
+
+    #[test]
+    fn distant_blob_starts_a_new_track() {
+        let mut tracker = Tracker::new(8.0, 2);
+        let first = tracker.update(at(0), &[blob(0, 0, 4, 4)]);
+        let second = tracker.update(at(1), &[blob(100, 100, 4, 4)]);
+        assert!(first != second);
+        assert_eq!(tracker.tracks().len(), 2);
+    }
+
+    #[test]
+    fn missed_tracks_are_dropped_after_max_missed() {
+        let mut tracker = Tracker::new(8.0, 2);
+        tracker.update(at(0), &[blob(0, 0, 4, 4)]);
+        tracker.update(at(1), &[]);
+        tracker.update(at(2), &[]);
+        assert_eq!(tracker.tracks().len(), 1);
+        assert_eq!(tracker.tracks()[0].misses, 2);
+        tracker.update(at(3), &[]);
+        assert!(tracker.tracks().is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt;
+use std::str::FromStr;
+
+use time::Timespec;
+
+use tracker::Track;
+
+/// Which way across a tripwire a track moved.
+///
+/// Looking along the line from `from` to `to` on screen, forward is from its
+/// left-hand side to its right-hand side; for a line drawn left to right,
+/// that's downwards.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Direction, String> {
+        match s {
+            "forward" => Ok(Direction::Forward),
+            "backward" => Ok(Direction::Backward),
+            other => Err(format!("unknown direction {:?}, expected forward or backward", other)),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Direction::Forward => "forward",
+            Direction::Backward => "backward",
+        })
+    }
+}
+
+/// A line segment in capture coordinates that fires when a track's
+/// centroid crosses it.
+#[derive(Clone, Debug)]
+pub struct Tripwire {
+    pub name: String,
+    pub from: (f32, f32),
+    pub to: (f32, f32),
+    /// Only crossings this way fire; `None` fires both ways.
+    pub direction: Option<Direction>,
+}
+
+impl Tripwire {
+    /// The direction `track` crossed the line in since its previous
+    /// position, if it did.
+    pub fn crossed_by(&self, track: &Track) -> Option<Direction> {
+        let prev = match track.prev_centroid {
+            Some(prev) => prev,
+            None => return None,
+        };
+        let curr = track.centroid;
+
+        let before = side(self.from, self.to, prev);
+        let after = side(self.from, self.to, curr);
+        let direction = if before < 0.0 && 0.0 <= after {
+            Direction::Forward
+        } else if 0.0 <= before && after < 0.0 {
+            Direction::Backward
+        } else {
+            return None;
+        };
+
+        // the centroid's path has to pass between the ends of the line, not
+        // just across its extension.
+        let from_side = side(prev, curr, self.from);
+        let to_side = side(prev, curr, self.to);
+        if 0.0 < from_side * to_side {
+            return None;
+        }
+
+        match self.direction {
+            Some(wanted) if wanted != direction => None,
+            _ => Some(direction),
+        }
+    }
+}
+
+/// A track crossing a tripwire.
+#[derive(Clone, Debug)]
+pub struct Crossing {
+    pub tripwire: String,
+    pub direction: Direction,
+    pub track_id: u64,
+    pub when: Timespec,
+    /// Crossings of this tripwire in this direction so far, this one included.
+    pub count: u64,
+}
+
+// Positive when `p` is right of the line from `a` to `b`, with y pointing down.
+fn side(a: (f32, f32), b: (f32, f32), p: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Timespec;
+
+    use blob::BoundingBox;
+    use tracker::Track;
+
+    use super::{Direction, Tripwire};
+
+    // A horizontal line across the middle of the left half of a 400x200
+    // frame, drawn left to right, so forward is downwards.
+    fn tripwire(direction: Option<Direction>) -> Tripwire {
+        Tripwire {
+            name: "door".to_string(),
+            from: (0.0, 100.0),
+            to: (200.0, 100.0),
+            direction: direction,
+        }
+    }
+
+    fn track(prev: (f32, f32), curr: (f32, f32)) -> Track {
+        Track {
+            id: 1,
+            bbox: BoundingBox { x0: curr.0 as u32, y0: curr.1 as u32, x1: curr.0 as u32 + 1, y1: curr.1 as u32 + 1 },
+            centroid: curr,
+            prev_centroid: Some(prev),
+            velocity: (0.0, 0.0),
+            area: 1,
+            first_seen: Timespec::new(0, 0),
+            last_seen: Timespec::new(1, 0),
+            hits: 2,
+            misses: 0,
+        }
+    }
+
+    #[test]
+    fn downwards_across_a_left_to_right_line_is_forward() {
+        let wire = tripwire(None);
+        assert_eq!(wire.crossed_by(&track((100.0, 50.0), (100.0, 150.0))), Some(Direction::Forward));
+    }
+
+    #[test]
+    fn upwards_across_a_left_to_right_line_is_backward() {
+        let wire = tripwire(None);
+        assert_eq!(wire.crossed_by(&track((100.0, 150.0), (100.0, 50.0))), Some(Direction::Backward));
+    }
+
+    #[test]
+    fn reversing_the_line_reverses_the_direction() {
+        let mut wire = tripwire(None);
+        ::std::mem::swap(&mut wire.from, &mut wire.to);
+        assert_eq!(wire.crossed_by(&track((100.0, 50.0), (100.0, 150.0))), Some(Direction::Backward));
+    }
+
+    #[test]
+    fn passing_beyond_the_end_does_not_cross() {
+        let wire = tripwire(None);
+        assert_eq!(wire.crossed_by(&track((300.0, 50.0), (300.0, 150.0))), None);
+        // diagonally, crossing y = 100 at x = 205, just past the end.
+        assert_eq!(wire.crossed_by(&track((195.0, 90.0), (265.0, 160.0))), None);
+    }
+
+    #[test]
+    fn staying_on_one_side_does_not_cross() {
+        let wire = tripwire(None);
+        assert_eq!(wire.crossed_by(&track((50.0, 20.0), (150.0, 90.0))), None);
+    }
+
+    #[test]
+    fn a_new_track_has_not_crossed() {
+        let wire = tripwire(None);
+        let mut fresh = track((100.0, 50.0), (100.0, 150.0));
+        fresh.prev_centroid = None;
+        assert_eq!(wire.crossed_by(&fresh), None);
+    }
+
+    #[test]
+    fn only_the_wanted_direction_fires() {
+        let wire = tripwire(Some(Direction::Forward));
+        assert_eq!(wire.crossed_by(&track((100.0, 50.0), (100.0, 150.0))), Some(Direction::Forward));
+        assert_eq!(wire.crossed_by(&track((100.0, 150.0), (100.0, 50.0))), None);
+    }
+}
@@ -1,4 +1,10 @@
-use std::mem::{self, transmute};
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::slice;
+use std::str::FromStr;
 use std::fs::{self, File};
 use std::io::{self, Read, Write};
 use std::ops::Deref;
@@ -18,58 +24,257 @@ use webp_sys::{
     WebPValidateConfig,
     WebPPicture,
     WebPPictureInitInternal,
-    WebPPictureAlloc,
     Enum_WebPPreset,
-    WebPMemoryWriter,
-    WebPMemoryWriterInit,
     Enum_WebPEncCSP,
-    WebPMemoryWrite,
+    Enum_WebPEncodingError,
     WebPEncode,
     WebPPictureFree,
 };
 
 
-pub fn reencode<S>(yuv: &Surface<Yuv420p, u8, S>)
-    -> Vec<u8>
-    where
-        S: Deref<Target=[u8]>
-{
-    let (y_p, u_p, v_p) = yuv.get_planes();
-    let mut output = vec![0; yuv.width() as usize * yuv.height() as usize * 4];
+/// libwebp's starting points for the encoder settings, by kind of image.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Preset {
+    Default,
+    Picture,
+    Photo,
+    Drawing,
+    Icon,
+    Text,
+}
+
+impl Preset {
+    fn to_sys(&self) -> Enum_WebPPreset {
+        match *self {
+            Preset::Default => Enum_WebPPreset::WEBP_PRESET_DEFAULT,
+            Preset::Picture => Enum_WebPPreset::WEBP_PRESET_PICTURE,
+            Preset::Photo => Enum_WebPPreset::WEBP_PRESET_PHOTO,
+            Preset::Drawing => Enum_WebPPreset::WEBP_PRESET_DRAWING,
+            Preset::Icon => Enum_WebPPreset::WEBP_PRESET_ICON,
+            Preset::Text => Enum_WebPPreset::WEBP_PRESET_TEXT,
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Preset, String> {
+        match s {
+            "default" => Ok(Preset::Default),
+            "picture" => Ok(Preset::Picture),
+            "photo" => Ok(Preset::Photo),
+            "drawing" => Ok(Preset::Drawing),
+            "icon" => Ok(Preset::Icon),
+            "text" => Ok(Preset::Text),
+            other => Err(format!(
+                "unknown preset {:?}, expected default, picture, photo, drawing, icon or text", other)),
+        }
+    }
+}
+
+/// WebP encoder settings. Anything left as `None` keeps the preset's value.
+#[derive(Clone, Copy, Debug)]
+pub struct EncoderConfig {
+    pub preset: Preset,
+    /// 0 to 100; in lossless mode, how hard to try rather than how lossy.
+    pub quality: f32,
+    /// Speed against size, 0 (fastest) to 6 (smallest).
+    pub method: Option<u8>,
+    pub lossless: bool,
+    /// Aim for this many bytes per frame; overrides `quality`.
+    pub target_size: Option<u32>,
+    /// Aim for this PSNR in dB; overrides `quality` and `target_size`.
+    pub target_psnr: Option<f32>,
+    /// Deblocking filter strength, 0 (off) to 100.
+    pub filter_strength: Option<u8>,
+    /// Number of segments, 1 to 4.
+    pub segments: Option<u8>,
+}
 
-    unsafe {
+impl Default for EncoderConfig {
+    fn default() -> EncoderConfig {
+        EncoderConfig {
+            preset: Preset::Picture,
+            quality: 70.0,
+            method: None,
+            lossless: false,
+            target_size: None,
+            target_psnr: None,
+            filter_strength: None,
+            segments: None,
+        }
+    }
+}
+
+impl EncoderConfig {
+    // Fills a libwebp config from the preset and our overrides.
+    unsafe fn to_sys(&self) -> Result<WebPConfig, EncodeError> {
         let mut config: WebPConfig = mem::zeroed();
-        assert_eq!(1, WebPConfigInitInternal(
-            &mut config as *mut _, Enum_WebPPreset::WEBP_PRESET_PICTURE,
-            70.0, 0x0202));
-        assert_eq!(1, WebPValidateConfig(&mut config as *mut _));
+        if WebPConfigInitInternal(&mut config as *mut _, self.preset.to_sys(), self.quality, 0x0202) != 1 {
+            return Err(EncodeError::VersionMismatch);
+        }
 
-        let mut pic: WebPPicture = mem::zeroed();
-        assert_eq!(1, WebPPictureInitInternal(&mut pic as *mut _, 0x0202));
-        pic.width = yuv.width() as i32;
-        pic.height = yuv.height() as i32;
-        assert_eq!(1, WebPPictureAlloc(&mut pic as *mut _));
+        config.lossless = self.lossless as i32;
+        if let Some(method) = self.method {
+            config.method = method as i32;
+        }
+        if let Some(target_size) = self.target_size {
+            config.target_size = target_size as i32;
+        }
+        if let Some(target_psnr) = self.target_psnr {
+            config.target_PSNR = target_psnr;
+        }
+        if let Some(filter_strength) = self.filter_strength {
+            config.filter_strength = filter_strength as i32;
+        }
+        if let Some(segments) = self.segments {
+            config.segments = segments as i32;
+        }
+
+        if WebPValidateConfig(&config as *const _) != 1 {
+            return Err(EncodeError::InvalidConfig);
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug)]
+pub enum EncodeError {
+    /// The linked libwebp doesn't speak the ABI version we were built for.
+    VersionMismatch,
+    /// libwebp rejected the encoder settings.
+    InvalidConfig,
+    /// Encoding failed, with the reason libwebp gave.
+    Encode(Enum_WebPEncodingError),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EncodeError::VersionMismatch => write!(f, "libwebp version mismatch"),
+            EncodeError::InvalidConfig => write!(f, "invalid WebP encoder configuration"),
+            EncodeError::Encode(code) => write!(f, "WebP encoding failed: {}", describe(code)),
+        }
+    }
+}
+
+impl Error for EncodeError {
+    fn description(&self) -> &str {
+        match *self {
+            EncodeError::VersionMismatch => "libwebp version mismatch",
+            EncodeError::InvalidConfig => "invalid WebP encoder configuration",
+            EncodeError::Encode(_) => "WebP encoding failed",
+        }
+    }
+}
+
+fn describe(code: Enum_WebPEncodingError) -> &'static str {
+    match code {
+        Enum_WebPEncodingError::VP8_ENC_OK => "no error reported",
+        Enum_WebPEncodingError::VP8_ENC_ERROR_OUT_OF_MEMORY => "out of memory",
+        Enum_WebPEncodingError::VP8_ENC_ERROR_BITSTREAM_OUT_OF_MEMORY => "out of memory flushing bits",
+        Enum_WebPEncodingError::VP8_ENC_ERROR_NULL_PARAMETER => "null parameter",
+        Enum_WebPEncodingError::VP8_ENC_ERROR_INVALID_CONFIGURATION => "invalid configuration",
+        Enum_WebPEncodingError::VP8_ENC_ERROR_BAD_DIMENSION => "bad picture dimensions",
+        Enum_WebPEncodingError::VP8_ENC_ERROR_PARTITION0_OVERFLOW => "partition 0 is too big",
+        Enum_WebPEncodingError::VP8_ENC_ERROR_PARTITION_OVERFLOW => "a partition is too big",
+        Enum_WebPEncodingError::VP8_ENC_ERROR_BAD_WRITE => "error while writing bytes",
+        Enum_WebPEncodingError::VP8_ENC_ERROR_FILE_TOO_BIG => "file is too big",
+        Enum_WebPEncodingError::VP8_ENC_ERROR_USER_ABORT => "aborted by user",
+        Enum_WebPEncodingError::VP8_ENC_ERROR_LAST => "unknown error",
+    }
+}
 
+/// Encodes frames one after another with settings validated once.
+///
+/// The picture borrows each frame's planes rather than copying them, and
+/// the output goes into one buffer reused for every frame.
+pub struct WebpEncoder {
+    config: WebPConfig,
+    pic: WebPPicture,
+    output: Vec<u8>,
+}
+
+// The picture's plane and output pointers are only set for the duration of
+// `encode`, which borrows what they point at, so nothing is shared between
+// threads through them.
+unsafe impl Send for WebpEncoder {}
+
+impl WebpEncoder {
+    pub fn new(config: EncoderConfig) -> Result<WebpEncoder, EncodeError> {
+        unsafe {
+            let config = try!(config.to_sys());
+
+            let mut pic: WebPPicture = mem::zeroed();
+            if WebPPictureInitInternal(&mut pic as *mut _, 0x0202) != 1 {
+                return Err(EncodeError::VersionMismatch);
+            }
+            pic.writer = Some(append_to_vec);
+
+            Ok(WebpEncoder {
+                config: config,
+                pic: pic,
+                output: Vec::new(),
+            })
+        }
+    }
+
+    /// Encodes `yuv`, returning the WebP file. It's only valid until the
+    /// next call.
+    pub fn encode<S>(&mut self, yuv: &Surface<Yuv420p, u8, S>) -> Result<&[u8], EncodeError>
+        where S: Deref<Target=[u8]>
+    {
+        let (y_p, u_p, v_p) = yuv.get_planes();
+        self.output.clear();
+
+        let pic = &mut self.pic;
         pic.use_argb = 0;
         pic.colorspace = Enum_WebPEncCSP::WEBP_YUV420;
-        pic.y = transmute(y_p.as_ptr());
+        pic.width = yuv.width() as i32;
+        pic.height = yuv.height() as i32;
+        // libwebp only reads the planes when encoding from YUV.
+        pic.y = y_p.as_ptr() as *mut u8;
         pic.y_stride = yuv.width() as i32;
-        pic.u = transmute(u_p.as_ptr());
-        pic.v = transmute(v_p.as_ptr());
+        pic.u = u_p.as_ptr() as *mut u8;
+        pic.v = v_p.as_ptr() as *mut u8;
         pic.uv_stride = yuv.width() as i32 / 2;
+        pic.custom_ptr = &mut self.output as *mut Vec<u8> as *mut c_void;
+
+        let encoded = unsafe {
+            let encoded = WebPEncode(&self.config as *const _, pic as *mut _);
+            // only frees what libwebp allocated itself, such as the ARGB
+            // copy lossless mode converts to, and clears the plane pointers.
+            WebPPictureFree(pic as *mut _);
+            encoded
+        };
+        pic.custom_ptr = ptr::null_mut();
+        if encoded != 1 {
+            return Err(EncodeError::Encode(pic.error_code));
+        }
+
+        Ok(&self.output[..])
+    }
 
-        let mut writer: WebPMemoryWriter = mem::zeroed();
-        WebPMemoryWriterInit(&mut writer as *mut _);
-        writer.mem = output.as_mut_ptr();
-        writer.max_size = output.len() as u64;
-        pic.writer = Some(WebPMemoryWrite);
-        pic.custom_ptr = &mut writer as *mut WebPMemoryWriter as *mut ::std::os::raw::c_void;
-        assert_eq!(1, WebPEncode(&mut config as *mut _, &mut pic as *mut _));
-        output.truncate(writer.size as usize);
-        assert_eq!(output.len() as u64, writer.size);
-        WebPPictureFree(&mut pic as *mut _);
+    /// Takes the buffer holding the last frame encoded, leaving `spare` to
+    /// be encoded into next, so a caller can keep frames without copying.
+    pub fn swap_output(&mut self, spare: Vec<u8>) -> Vec<u8> {
+        mem::replace(&mut self.output, spare)
     }
+}
 
-    output
+// libwebp's output callback; `custom_ptr` is the encoder's output `Vec`.
+unsafe extern "C" fn append_to_vec(data: *const u8, data_size: u64, picture: *const WebPPicture) -> c_int {
+    let output = &mut *((*picture).custom_ptr as *mut Vec<u8>);
+    output.extend_from_slice(slice::from_raw_parts(data, data_size as usize));
+    1
 }
 
+/// Encodes a single frame. Prefer a `WebpEncoder` for a stream of them.
+pub fn reencode<S>(yuv: &Surface<Yuv420p, u8, S>, encoder: &EncoderConfig)
+    -> Result<Vec<u8>, EncodeError>
+    where
+        S: Deref<Target=[u8]>
+{
+    try!(WebpEncoder::new(*encoder)).encode(yuv).map(|webp| webp.to_vec())
+}